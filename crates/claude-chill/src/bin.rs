@@ -1,9 +1,10 @@
 mod cli;
 
 use clap::Parser;
-use claude_chill::config::Config;
-use claude_chill::key_parser;
+use claude_chill::config::{Config, LookbackMode};
+use claude_chill::keymap::{KeyAction, Keymap};
 use claude_chill::proxy::{Proxy, ProxyConfig};
+use claude_chill::sessions::{self, SessionStore};
 use log::debug;
 use std::process::ExitCode;
 
@@ -25,49 +26,61 @@ fn main() -> ExitCode {
     }
 
     let cli = cli::Cli::parse();
-    let config = Config::load();
-
-    let history_lines = cli.history_lines.unwrap_or(config.history_lines);
+    if let Some(cli::CliCommand::Sessions(command)) = &cli.subcommand {
+        return run_sessions_command(command);
+    }
 
-    let lookback_key = cli
-        .lookback_key
-        .clone()
-        .unwrap_or_else(|| config.lookback_key.clone());
+    let mut config = Config::load();
+    if let Some(refresh_rate) = cli.refresh_rate {
+        config.refresh_rate = refresh_rate;
+    }
 
-    let (lookback_sequence_legacy, lookback_sequence_kitty) = match key_parser::parse(&lookback_key)
-    {
-        Ok(key) => {
-            let legacy = key.to_escape_sequence();
-            let kitty = key.to_kitty_sequence().unwrap_or_else(|| legacy.clone());
-            (legacy, kitty)
-        }
-        Err(e) => {
-            eprintln!("Invalid lookback key '{}': {}", lookback_key, e);
-            eprintln!("Using default: [ctrl][6]");
-            (vec![0x1E], b"\x1b[54;5u".to_vec())
-        }
-    };
+    let history_lines = cli.history_lines.unwrap_or(config.history_lines);
 
-    debug!(
-        "Lookback sequences: legacy={:?} kitty={:?}",
-        lookback_sequence_legacy, lookback_sequence_kitty
-    );
+    if let Some(lookback_key) = cli.lookback_key.clone() {
+        config.lookback_key = lookback_key;
+        config.keys.remove(&KeyAction::ToggleLookback);
+    }
+    let keymap = build_keymap(&config);
 
     let auto_lookback_timeout_ms = cli
         .auto_lookback_timeout
         .unwrap_or(config.auto_lookback_timeout_ms);
 
+    let lookback_mode = if cli.pager {
+        LookbackMode::Pager
+    } else {
+        config.lookback_mode
+    };
+
     let proxy_config = ProxyConfig {
         max_history_lines: history_lines,
-        lookback_key,
-        lookback_sequence_legacy,
-        lookback_sequence_kitty,
+        max_history_bytes: config.history_bytes,
+        max_history_generations: config.history_generations,
+        keymap,
         auto_lookback_timeout_ms,
+        confirm_timeout_ms: config.confirm_timeout_ms,
+        redraw_throttle_ms: config.redraw_throttle_ms(),
+        lookback_mode,
+        lookback_input: config.lookback_input,
+        lookback_cache_bytes: config.lookback_cache_bytes,
+        history_mode: config.history_mode,
+        dedupe_frames: config.dedupe_frames,
+        linkify: config.linkify,
+        forward: config.forward,
+        export_path: config.export_path.clone(),
+        export_on_exit: cli.export_on_exit.clone(),
+        session_dir: config
+            .save_sessions
+            .then(SessionStore::default_dir)
+            .flatten(),
+        max_sessions: config.max_sessions,
     };
 
+    let command = cli.command.as_deref().unwrap_or_default();
     let cmd_args: Vec<&str> = cli.args.iter().map(|s| s.as_str()).collect();
 
-    match Proxy::spawn(&cli.command, &cmd_args, proxy_config) {
+    match Proxy::spawn(command, &cmd_args, proxy_config) {
         Ok(mut proxy) => match proxy.run() {
             Ok(exit_code) => ExitCode::from(exit_code as u8),
             Err(e) => {
@@ -81,3 +94,63 @@ fn main() -> ExitCode {
         }
     }
 }
+
+/// Bind the keys configured for each action and guard the confirm keys.
+/// Invalid keys are skipped, except that lookback falls back to its default
+/// key rather than becoming unreachable.
+fn build_keymap(config: &Config) -> Keymap {
+    let mut keymap = Keymap::new();
+    for action in KeyAction::ALL {
+        for key in config.action_keys(action) {
+            if let Err(e) = keymap.bind(&key, action) {
+                eprintln!("Invalid {} key '{}': {}", action, key, e);
+            }
+        }
+    }
+    if !keymap.is_bound(KeyAction::ToggleLookback) {
+        let default = Config::default().lookback_key;
+        eprintln!("Using default lookback key: {}", default);
+        keymap
+            .bind(&default, KeyAction::ToggleLookback)
+            .expect("default keys are valid");
+    }
+    for key in &config.confirm_keys {
+        if let Err(e) = keymap.guard(key) {
+            eprintln!("Invalid confirm key '{}': {}", key, e);
+        }
+    }
+    debug!("Keymap: {:?}", keymap);
+    keymap
+}
+
+fn run_sessions_command(command: &cli::SessionsCommand) -> ExitCode {
+    let Some(dir) = SessionStore::default_dir() else {
+        eprintln!("No state directory available for saved sessions");
+        return ExitCode::from(1);
+    };
+    let store = SessionStore::new(dir);
+    let mut stdout = std::io::stdout().lock();
+
+    let result = match command {
+        cli::SessionsCommand::List => sessions::write_list(&store, &mut stdout),
+        cli::SessionsCommand::Show { id, plain } => {
+            sessions::write_show(&store, id.as_deref(), *plain, &mut stdout)
+        }
+        cli::SessionsCommand::Grep { pattern } => {
+            match sessions::write_grep(&store, pattern, &mut stdout) {
+                // Exit status follows grep: 1 when nothing matched
+                Ok(0) => return ExitCode::from(1),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::from(1)
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
 
@@ -6,11 +7,17 @@ const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"),
 #[command(
     name = "claude-chill",
     version = VERSION,
-    about = "A PTY proxy that tames Claude Code's massive terminal updates"
+    about = "A PTY proxy that tames Claude Code's massive terminal updates",
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
 )]
 pub struct Cli {
+    #[command(subcommand)]
+    pub subcommand: Option<CliCommand>,
+
     /// Command to run (e.g., "claude")
-    pub command: String,
+    #[arg(required = true)]
+    pub command: Option<String>,
 
     /// Arguments to pass to the command
     #[arg(trailing_var_arg = true)]
@@ -27,4 +34,45 @@ pub struct Cli {
     /// Auto-lookback timeout in ms, 0 to disable (default: 15000)
     #[arg(short = 'a', long = "auto-lookback-timeout")]
     pub auto_lookback_timeout: Option<u64>,
+
+    /// Max rendered frames per second (default: 20)
+    #[arg(short = 'r', long = "refresh-rate")]
+    pub refresh_rate: Option<u64>,
+
+    /// Page through history inside the proxy instead of dumping it to the terminal
+    #[arg(short = 'p', long = "pager")]
+    pub pager: bool,
+
+    /// Write history to this file when the command exits (.html, .ansi or plain text)
+    #[arg(long = "export-on-exit", value_name = "PATH")]
+    pub export_on_exit: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CliCommand {
+    /// Browse saved session history
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionsCommand {
+    /// List saved sessions, oldest first
+    List,
+
+    /// Print a session's history (default: the latest session)
+    Show {
+        /// Session id or a unique prefix of it
+        id: Option<String>,
+
+        /// Strip escape sequences
+        #[arg(long)]
+        plain: bool,
+    },
+
+    /// Search all saved sessions for a regex
+    Grep {
+        /// Regex matched against each line with escape sequences stripped
+        pattern: String,
+    },
 }
@@ -1,28 +1,161 @@
 use crate::key_parser::{self, KeyCombination};
+use crate::keymap::KeyAction;
+use crate::side_channel::SideChannel;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
 const DEFAULT_LOOKBACK_KEY: &str = "[ctrl][6]";
+const DEFAULT_EXIT_LOOKBACK_KEY: &str = "[ctrl][c]";
+
+/// The key after `prefix_key` that runs each action by default.
+const PREFIX_CHORD_KEYS: [(KeyAction, &str); 7] = [
+    (KeyAction::ToggleLookback, "[l]"),
+    (KeyAction::TogglePeek, "[p]"),
+    (KeyAction::DumpHistory, "[d]"),
+    (KeyAction::ExportHistory, "[s]"),
+    (KeyAction::ForceRedraw, "[r]"),
+    (KeyAction::TogglePassthrough, "[t]"),
+    (KeyAction::ClearHistory, "[c]"),
+];
 const DEFAULT_REFRESH_RATE: u64 = 20;
 const DEFAULT_AUTO_LOOKBACK_TIMEOUT_MS: u64 = 15000;
+const DEFAULT_HISTORY_BYTES: usize = 64 * 1024 * 1024;
+const DEFAULT_HISTORY_GENERATIONS: usize = 5;
+const DEFAULT_MAX_SESSIONS: usize = 100;
+const DEFAULT_LOOKBACK_CACHE_BYTES: usize = 8 * 1024 * 1024;
+const DEFAULT_CONFIRM_TIMEOUT_MS: u64 = 2000;
+
+/// How lookback mode presents history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LookbackMode {
+    /// Write the whole history to the terminal and use its scrollback
+    #[default]
+    Dump,
+    /// Page through history inside the proxy
+    Pager,
+}
+
+/// What happens to keys typed in dump-mode lookback that aren't lookback
+/// commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LookbackInput {
+    /// Drop them
+    #[default]
+    Discard,
+    /// Hold them and send them to the child when lookback exits
+    Queue,
+    /// Exit lookback and send them to the child
+    Exit,
+}
+
+/// What lookback history is built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryMode {
+    /// The output stream as the child wrote it
+    #[default]
+    Raw,
+    /// Rows scrolling off the VT emulator's screen, reflowed to the current
+    /// width on replay
+    Screen,
+}
+
+/// Which side channels (output the renderer would drop) are forwarded to
+/// the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ForwardPolicy {
+    pub title: bool,
+    pub notification: bool,
+    pub clipboard: bool,
+    pub cwd: bool,
+    pub bell: bool,
+}
+
+impl Default for ForwardPolicy {
+    fn default() -> Self {
+        Self {
+            title: true,
+            notification: true,
+            clipboard: true,
+            cwd: true,
+            bell: true,
+        }
+    }
+}
+
+impl ForwardPolicy {
+    pub fn allows(&self, channel: SideChannel) -> bool {
+        match channel {
+            SideChannel::Title => self.title,
+            SideChannel::Notification => self.notification,
+            SideChannel::Clipboard => self.clipboard,
+            SideChannel::Cwd => self.cwd,
+            SideChannel::Bell => self.bell,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub history_lines: usize,
+    pub history_bytes: usize,
+    pub history_generations: usize,
     pub lookback_key: String,
+    /// Unbound by default: every spare Ctrl combination reaches the child as
+    /// some control character
+    pub peek_key: Option<String>,
+    /// Prefix for tmux-style chords, e.g. `[ctrl][a]` then `[l]` for lookback
+    pub prefix_key: Option<String>,
+    /// Keys for each action, replacing that action's default keys
+    pub keys: HashMap<KeyAction, Vec<String>>,
+    /// Keys that are only sent to the child when pressed twice in a row
+    pub confirm_keys: Vec<String>,
+    pub confirm_timeout_ms: u64,
     pub refresh_rate: u64,
     pub auto_lookback_timeout_ms: u64,
+    pub lookback_mode: LookbackMode,
+    pub lookback_input: LookbackInput,
+    pub lookback_cache_bytes: usize,
+    pub history_mode: HistoryMode,
+    pub dedupe_frames: bool,
+    /// Draw URLs and `file.rs:123` references on screen as links
+    pub linkify: bool,
+    pub forward: ForwardPolicy,
+    pub export_path: Option<PathBuf>,
+    pub save_sessions: bool,
+    pub max_sessions: usize,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             history_lines: 100_000,
+            history_bytes: DEFAULT_HISTORY_BYTES,
+            history_generations: DEFAULT_HISTORY_GENERATIONS,
             lookback_key: DEFAULT_LOOKBACK_KEY.to_string(),
+            peek_key: None,
+            prefix_key: None,
+            keys: HashMap::new(),
+            confirm_keys: Vec::new(),
+            confirm_timeout_ms: DEFAULT_CONFIRM_TIMEOUT_MS,
             refresh_rate: DEFAULT_REFRESH_RATE,
             auto_lookback_timeout_ms: DEFAULT_AUTO_LOOKBACK_TIMEOUT_MS,
+            lookback_mode: LookbackMode::default(),
+            lookback_input: LookbackInput::default(),
+            lookback_cache_bytes: DEFAULT_LOOKBACK_CACHE_BYTES,
+            history_mode: HistoryMode::default(),
+            dedupe_frames: false,
+            linkify: false,
+            forward: ForwardPolicy::default(),
+            export_path: None,
+            save_sessions: false,
+            max_sessions: DEFAULT_MAX_SESSIONS,
         }
     }
 }
@@ -83,6 +216,27 @@ impl Config {
             })
     }
 
+    /// Keys bound to `action`: those in the `[keys]` table if it lists the
+    /// action, otherwise its defaults, including a chord if `prefix_key` is
+    /// set.
+    pub fn action_keys(&self, action: KeyAction) -> Vec<String> {
+        if let Some(keys) = self.keys.get(&action) {
+            return keys.clone();
+        }
+        let mut keys = match action {
+            KeyAction::ToggleLookback => vec![self.lookback_key.clone()],
+            KeyAction::TogglePeek => self.peek_key.iter().cloned().collect(),
+            KeyAction::ExitLookback => vec![DEFAULT_EXIT_LOOKBACK_KEY.to_string()],
+            _ => Vec::new(),
+        };
+        if let Some(prefix) = &self.prefix_key
+            && let Some((_, key)) = PREFIX_CHORD_KEYS.iter().find(|(a, _)| *a == action)
+        {
+            keys.push(format!("{} {}", prefix, key));
+        }
+        keys
+    }
+
     pub fn redraw_throttle_ms(&self) -> u64 {
         let rate = self.refresh_rate.max(1);
         1000 / rate
@@ -97,10 +251,140 @@ mod tests {
     fn test_default_config() {
         let config = Config::default();
         assert_eq!(config.history_lines, 100_000);
+        assert_eq!(config.history_bytes, 64 * 1024 * 1024);
+        assert_eq!(config.history_generations, 5);
         assert_eq!(config.lookback_key, "[ctrl][6]");
+        assert_eq!(config.peek_key, None);
+        assert_eq!(config.prefix_key, None);
+        assert!(config.keys.is_empty());
+        assert!(config.confirm_keys.is_empty());
+        assert_eq!(config.confirm_timeout_ms, 2000);
         assert_eq!(config.refresh_rate, 20);
         assert_eq!(config.redraw_throttle_ms(), 50);
         assert_eq!(config.auto_lookback_timeout_ms, 15000);
+        assert_eq!(config.lookback_mode, LookbackMode::Dump);
+        assert_eq!(config.lookback_input, LookbackInput::Discard);
+        assert_eq!(config.lookback_cache_bytes, 8 * 1024 * 1024);
+        assert_eq!(config.history_mode, HistoryMode::Raw);
+        assert!(!config.dedupe_frames);
+        assert!(!config.linkify);
+        assert_eq!(config.forward, ForwardPolicy::default());
+        assert_eq!(config.export_path, None);
+        assert!(!config.save_sessions);
+        assert_eq!(config.max_sessions, 100);
+    }
+
+    #[test]
+    fn test_parse_lookback_mode() {
+        let config: Config = toml::from_str("lookback_mode = \"pager\"").unwrap();
+        assert_eq!(config.lookback_mode, LookbackMode::Pager);
+        assert_eq!(config.history_lines, 100_000);
+    }
+
+    #[test]
+    fn test_parse_lookback_input() {
+        let config: Config = toml::from_str("lookback_input = \"queue\"").unwrap();
+        assert_eq!(config.lookback_input, LookbackInput::Queue);
+        let config: Config = toml::from_str("lookback_input = \"exit\"").unwrap();
+        assert_eq!(config.lookback_input, LookbackInput::Exit);
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let config: Config = toml::from_str(
+            "lookback_key = \"[f11]\"\n\
+             [keys]\n\
+             toggle_lookback = [\"[ctrl][6]\", \"[f12]\"]\n\
+             clear_history = [\"[ctrl][shift][k]\"]\n\
+             exit_lookback = []\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.action_keys(KeyAction::ToggleLookback),
+            vec!["[ctrl][6]", "[f12]"]
+        );
+        assert_eq!(
+            config.action_keys(KeyAction::ClearHistory),
+            vec!["[ctrl][shift][k]"]
+        );
+        assert!(config.action_keys(KeyAction::ExitLookback).is_empty());
+        assert!(config.action_keys(KeyAction::TogglePeek).is_empty());
+        let config: Config = toml::from_str("peek_key = \"[f7]\"").unwrap();
+        assert_eq!(config.action_keys(KeyAction::TogglePeek), vec!["[f7]"]);
+        assert!(config.action_keys(KeyAction::DumpHistory).is_empty());
+        assert!(toml::from_str::<Config>("[keys]\nfly = [\"[f1]\"]").is_err());
+    }
+
+    #[test]
+    fn test_prefix_key_chords() {
+        let config: Config = toml::from_str(
+            "prefix_key = \"[ctrl][a]\"\n\
+             [keys]\n\
+             clear_history = [\"[ctrl][a] [x]\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.action_keys(KeyAction::ToggleLookback),
+            vec!["[ctrl][6]", "[ctrl][a] [l]"]
+        );
+        assert_eq!(
+            config.action_keys(KeyAction::DumpHistory),
+            vec!["[ctrl][a] [d]"]
+        );
+        assert_eq!(
+            config.action_keys(KeyAction::ClearHistory),
+            vec!["[ctrl][a] [x]"]
+        );
+        assert_eq!(
+            config.action_keys(KeyAction::ExitLookback),
+            vec!["[ctrl][c]"]
+        );
+    }
+
+    #[test]
+    fn test_parse_confirm_keys() {
+        let config: Config = toml::from_str(
+            "confirm_keys = [\"[ctrl][c]\", \"[ctrl][d]\"]\n\
+             confirm_timeout_ms = 1000\n",
+        )
+        .unwrap();
+        assert_eq!(config.confirm_keys, vec!["[ctrl][c]", "[ctrl][d]"]);
+        assert_eq!(config.confirm_timeout_ms, 1000);
+    }
+
+    #[test]
+    fn test_default_action_keys() {
+        let config = Config::default();
+        assert_eq!(
+            config.action_keys(KeyAction::ToggleLookback),
+            vec!["[ctrl][6]"]
+        );
+        assert_eq!(
+            config.action_keys(KeyAction::ExitLookback),
+            vec!["[ctrl][c]"]
+        );
+    }
+
+    #[test]
+    fn test_parse_history_mode() {
+        let config: Config = toml::from_str("history_mode = \"screen\"").unwrap();
+        assert_eq!(config.history_mode, HistoryMode::Screen);
+    }
+
+    #[test]
+    fn test_parse_forward_policy() {
+        let config: Config =
+            toml::from_str("[forward]\nclipboard = false\nbell = false\n").unwrap();
+        assert!(!config.forward.allows(SideChannel::Clipboard));
+        assert!(!config.forward.allows(SideChannel::Bell));
+        assert!(config.forward.allows(SideChannel::Title));
+        assert!(config.forward.allows(SideChannel::Notification));
+    }
+
+    #[test]
+    fn test_parse_export_path() {
+        let config: Config = toml::from_str("export_path = \"/tmp/history.html\"").unwrap();
+        assert_eq!(config.export_path, Some(PathBuf::from("/tmp/history.html")));
     }
 
     #[test]
@@ -9,6 +9,9 @@ pub const ALT_SCREEN_EXIT: &[u8] = b"\x1b[?1049l";
 pub const ALT_SCREEN_ENTER_LEGACY: &[u8] = b"\x1b[?47h";
 pub const ALT_SCREEN_EXIT_LEGACY: &[u8] = b"\x1b[?47l";
 
+pub const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+pub const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
+
 pub const SYNC_BUFFER_CAPACITY: usize = 1024 * 1024;
 pub const OUTPUT_BUFFER_CAPACITY: usize = 32768;
 pub const INPUT_BUFFER_CAPACITY: usize = 64;
@@ -0,0 +1,383 @@
+//! Export lookback history to files.
+//!
+//! History is stored as the (filtered) byte stream the child produced.
+//! Plain text and HTML exports replay it through a VT emulator the width of
+//! the terminal it was written for, so cursor movement and erases such as
+//! Claude's redraws are applied. Rows are taken as they scroll off the top,
+//! like screen history, with soft-wrapped rows joined into one line.
+
+use crate::screen_history::{self, SCROLLBACK_ROWS};
+use std::fmt::Write as FmtWrite;
+use std::path::Path;
+
+/// Rows of the emulator history is replayed on. Redraws move the cursor up
+/// at most the height of the terminal they were written for.
+const EXPORT_ROWS: u16 = 100;
+
+/// Width used when the terminal's isn't known.
+pub const DEFAULT_EXPORT_COLS: u16 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Escape sequences stripped
+    Text,
+    /// History bytes as stored, escape sequences included
+    Ansi,
+    /// Self-contained HTML page with colours as inline styles
+    Html,
+}
+
+impl ExportFormat {
+    /// Pick a format from the file extension, defaulting to plain text.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("html") | Some("htm") => ExportFormat::Html,
+            Some("ansi") | Some("ans") => ExportFormat::Ansi,
+            _ => ExportFormat::Text,
+        }
+    }
+}
+
+/// Render history written for a terminal `cols` wide in the given format.
+pub fn export(history: &[u8], format: ExportFormat, cols: u16) -> Vec<u8> {
+    match format {
+        ExportFormat::Text => to_plain_text(history, cols).into_bytes(),
+        ExportFormat::Ansi => history.to_vec(),
+        ExportFormat::Html => to_html(history, cols).into_bytes(),
+    }
+}
+
+/// Write history to `path`, choosing the format from its extension.
+pub fn write_export(path: &Path, history: &[u8], cols: u16) -> std::io::Result<()> {
+    std::fs::write(path, export(history, ExportFormat::from_path(path), cols))
+}
+
+pub fn to_plain_text(history: &[u8], cols: u16) -> String {
+    let mut output = String::new();
+    for line in parse_lines(history, cols) {
+        let text: String = line.iter().map(|cell| cell.text.as_str()).collect();
+        output.push_str(text.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+pub fn to_html(history: &[u8], cols: u16) -> String {
+    let mut output = String::from(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <title>claude-chill history</title>\n\
+         <style>\n\
+         body { background: #1e1e1e; color: #d4d4d4; margin: 0; }\n\
+         pre { font-family: ui-monospace, Menlo, Consolas, monospace; padding: 1em; margin: 0; }\n\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         <pre>",
+    );
+
+    for line in parse_lines(history, cols) {
+        let end = line
+            .iter()
+            .rposition(|cell| cell.text != " " || cell.style.has_background())
+            .map_or(0, |i| i + 1);
+        let mut run_style: Option<&Style> = None;
+        for cell in &line[..end] {
+            if run_style != Some(&cell.style) {
+                if run_style.is_some_and(|s| !s.is_default()) {
+                    output.push_str("</span>");
+                }
+                if !cell.style.is_default() {
+                    let _ = write!(output, "<span style=\"{}\">", cell.style.css());
+                }
+                run_style = Some(&cell.style);
+            }
+            for ch in cell.text.chars() {
+                push_html_escaped(&mut output, ch);
+            }
+        }
+        if run_style.is_some_and(|s| !s.is_default()) {
+            output.push_str("</span>");
+        }
+        output.push('\n');
+    }
+
+    output.push_str("</pre>\n</body>\n</html>\n");
+    output
+}
+
+fn push_html_escaped(output: &mut String, ch: char) {
+    match ch {
+        '&' => output.push_str("&amp;"),
+        '<' => output.push_str("&lt;"),
+        '>' => output.push_str("&gt;"),
+        '"' => output.push_str("&quot;"),
+        _ => output.push(ch),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Style {
+    foreground: vt100::Color,
+    background: vt100::Color,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            foreground: vt100::Color::Default,
+            background: vt100::Color::Default,
+            bold: false,
+            dim: false,
+            italic: false,
+            underline: false,
+            inverse: false,
+        }
+    }
+}
+
+impl Style {
+    fn of(cell: &vt100::Cell) -> Self {
+        Self {
+            foreground: cell.fgcolor(),
+            background: cell.bgcolor(),
+            bold: cell.bold(),
+            dim: cell.dim(),
+            italic: cell.italic(),
+            underline: cell.underline(),
+            inverse: cell.inverse(),
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Style::default()
+    }
+
+    fn has_background(&self) -> bool {
+        self.inverse || self.background != vt100::Color::Default
+    }
+
+    fn css(&self) -> String {
+        let (foreground, background) = if self.inverse {
+            (
+                css_color(&self.background).unwrap_or("#1e1e1e".to_string()),
+                css_color(&self.foreground).or(Some("#d4d4d4".to_string())),
+            )
+        } else {
+            (
+                css_color(&self.foreground).unwrap_or_default(),
+                css_color(&self.background),
+            )
+        };
+
+        let mut css = String::new();
+        if !foreground.is_empty() {
+            let _ = write!(css, "color:{};", foreground);
+        }
+        if let Some(background) = background {
+            let _ = write!(css, "background-color:{};", background);
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.6;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        if self.underline {
+            css.push_str("text-decoration:underline;");
+        }
+        css
+    }
+}
+
+fn css_color(color: &vt100::Color) -> Option<String> {
+    let (r, g, b) = match *color {
+        vt100::Color::Default => return None,
+        vt100::Color::Idx(index) => palette_rgb(index),
+        vt100::Color::Rgb(r, g, b) => (r, g, b),
+    };
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+/// xterm's default 256-colour palette.
+fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00),
+        (0xcd, 0x00, 0x00),
+        (0x00, 0xcd, 0x00),
+        (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee),
+        (0xcd, 0x00, 0xcd),
+        (0x00, 0xcd, 0xcd),
+        (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f),
+        (0xff, 0x00, 0x00),
+        (0x00, 0xff, 0x00),
+        (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff),
+        (0xff, 0x00, 0xff),
+        (0x00, 0xff, 0xff),
+        (0xff, 0xff, 0xff),
+    ];
+    match index {
+        0..=15 => ANSI[usize::from(index)],
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let i = index - 16;
+            (level(i / 36), level((i / 6) % 6), level(i % 6))
+        }
+        232..=255 => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cell {
+    text: String,
+    style: Style,
+}
+
+/// Replay history into lines of styled cells.
+fn parse_lines(history: &[u8], cols: u16) -> Vec<Vec<Cell>> {
+    let mut parser = vt100::Parser::new(EXPORT_ROWS, cols.max(1), SCROLLBACK_ROWS);
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut push_row = |screen: &vt100::Screen, row: u16| {
+        push_cells(screen, row, &mut line);
+        if !screen.row_wrapped(row) {
+            lines.push(std::mem::take(&mut line));
+        }
+    };
+    screen_history::process_rows(&mut parser, history, &mut push_row);
+    let screen = parser.screen();
+    for row in 0..screen_history::content_rows(screen) {
+        push_row(screen, row);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn push_cells(screen: &vt100::Screen, row: u16, line: &mut Vec<Cell>) {
+    for col in 0..screen.size().1 {
+        let Some(cell) = screen.cell(row, col) else {
+            continue;
+        };
+        if cell.is_wide_continuation() {
+            continue;
+        }
+        line.push(Cell {
+            text: if cell.has_contents() {
+                cell.contents().to_string()
+            } else {
+                " ".to_string()
+            },
+            style: Style::of(cell),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_format_from_extension() {
+        let format = |p: &str| ExportFormat::from_path(&PathBuf::from(p));
+        assert_eq!(format("out.html"), ExportFormat::Html);
+        assert_eq!(format("out.HTM"), ExportFormat::Html);
+        assert_eq!(format("out.ansi"), ExportFormat::Ansi);
+        assert_eq!(format("out.txt"), ExportFormat::Text);
+        assert_eq!(format("out"), ExportFormat::Text);
+    }
+
+    #[test]
+    fn test_plain_text_strips_escapes() {
+        let text = to_plain_text(b"\x1b[1;31mError:\x1b[0m failed\r\nnext line\r\n", 80);
+        assert_eq!(text, "Error: failed\nnext line\n");
+    }
+
+    #[test]
+    fn test_plain_text_applies_carriage_return() {
+        let text = to_plain_text(b"50%\r100%\r\n", 80);
+        assert_eq!(text, "100%\n");
+    }
+
+    #[test]
+    fn test_plain_text_erase_line() {
+        let text = to_plain_text(b"spinner...\r\x1b[Kdone\r\n", 80);
+        assert_eq!(text, "done\n");
+    }
+
+    #[test]
+    fn test_plain_text_tabs_and_backspace() {
+        let text = to_plain_text(b"a\tb\r\nab\x08c\r\n", 80);
+        assert_eq!(text, "a       b\nac\n");
+    }
+
+    #[test]
+    fn test_plain_text_applies_cursor_up_redraws() {
+        let text = to_plain_text(
+            b"one\r\nworking.\r\n\x1b[1A\x1b[2Kdone\r\n\x1b[31mtwo\x1b[0m\r\n",
+            80,
+        );
+        assert_eq!(text, "one\ndone\ntwo\n");
+    }
+
+    #[test]
+    fn test_plain_text_joins_wrapped_rows() {
+        let line = "x".repeat(30);
+        let text = to_plain_text(format!("{}\r\nnext\r\n", line).as_bytes(), 20);
+        assert_eq!(text, format!("{}\nnext\n", line));
+    }
+
+    #[test]
+    fn test_ansi_is_raw() {
+        let history = b"\x1b[31mred\x1b[0m\r\n";
+        assert_eq!(export(history, ExportFormat::Ansi, 80), history.to_vec());
+    }
+
+    #[test]
+    fn test_html_colors_and_escaping() {
+        let html = to_html(
+            b"\x1b[31m<red>\x1b[0m & \x1b[1;38;2;1;2;3mrgb\x1b[0m\r\n",
+            80,
+        );
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<span style=\"color:#cd0000;\">&lt;red&gt;</span> &amp; "));
+        assert!(html.contains("<span style=\"color:#010203;font-weight:bold;\">rgb</span>"));
+        assert!(html.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn test_html_inverse_swaps_colors() {
+        let html = to_html(b"\x1b[7mbar\x1b[0m\r\n", 80);
+        assert!(html.contains("color:#1e1e1e;background-color:#d4d4d4;"));
+    }
+
+    #[test]
+    fn test_palette_cube_and_grayscale() {
+        assert_eq!(palette_rgb(16), (0, 0, 0));
+        assert_eq!(palette_rgb(196), (255, 0, 0));
+        assert_eq!(palette_rgb(232), (8, 8, 8));
+        assert_eq!(palette_rgb(255), (238, 238, 238));
+    }
+}
@@ -0,0 +1,172 @@
+//! Deduplication of redrawn frames in history.
+//!
+//! Claude Code re-renders much of the same content in consecutive sync
+//! blocks. Each block is rendered on its own into styled lines and diffed
+//! against the previous block's lines, and only the lines that are new or
+//! changed go to history, so lookback reads like a transcript.
+
+use crate::screen_history;
+
+/// Frames are diffed line by line with an LCS table, which is skipped for
+/// frames too large to diff cheaply.
+const MAX_DIFF_CELLS: usize = 1 << 20;
+
+/// Upper bound on the rows of the scratch screen a block is rendered on.
+const MAX_FRAME_ROWS: usize = 4096;
+
+#[derive(Default)]
+pub struct FrameDedup {
+    previous: Vec<Vec<u8>>,
+}
+
+impl FrameDedup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget the previous frame, e.g. after a full redraw.
+    pub fn reset(&mut self) {
+        self.previous.clear();
+    }
+
+    /// Render a sync block and append the lines that weren't in the previous
+    /// frame to `output`, each ending in CRLF. Returns false without
+    /// appending anything if the block can't be rendered as a frame (it
+    /// switches to the alternate screen), in which case the caller should
+    /// store it as is.
+    pub fn push_frame(&mut self, block: &[u8], rows: u16, cols: u16, output: &mut Vec<u8>) -> bool {
+        let newlines = memchr::memchr_iter(b'\n', block).count();
+        let frame_rows = (newlines + usize::from(rows)).min(MAX_FRAME_ROWS) as u16;
+        let mut parser = vt100::Parser::new(frame_rows.max(1), cols.max(1), 0);
+        parser.process(block);
+        if parser.screen().alternate_screen() {
+            return false;
+        }
+
+        let lines = screen_history::screen_lines(parser.screen());
+        for index in new_lines(&self.previous, &lines) {
+            output.extend_from_slice(&lines[index]);
+            output.extend_from_slice(b"\r\n");
+        }
+        self.previous = lines;
+        true
+    }
+}
+
+/// Indexes of lines in `current` that aren't part of the longest common
+/// subsequence with `previous`.
+fn new_lines(previous: &[Vec<u8>], current: &[Vec<u8>]) -> Vec<usize> {
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &previous[prefix..previous.len() - suffix];
+    let new = &current[prefix..current.len() - suffix];
+    let new_range = prefix..current.len() - suffix;
+
+    if old.is_empty() || new.is_empty() || old.len() * new.len() > MAX_DIFF_CELLS {
+        return new_range.collect();
+    }
+
+    // lengths[i][j] = LCS length of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut inserted = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while j < new.len() {
+        if i < old.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            inserted.push(prefix + j);
+            j += 1;
+        }
+    }
+    inserted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<Vec<u8>> {
+        text.iter().map(|l| l.as_bytes().to_vec()).collect()
+    }
+
+    fn push(dedup: &mut FrameDedup, block: &[u8]) -> String {
+        let mut output = Vec::new();
+        assert!(dedup.push_frame(block, 5, 40, &mut output));
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_new_lines_diff() {
+        let previous = lines(&["a", "b", "spin 1"]);
+        assert_eq!(new_lines(&previous, &lines(&["a", "b", "spin 1"])), vec![]);
+        assert_eq!(new_lines(&previous, &lines(&["a", "b", "spin 2"])), vec![2]);
+        assert_eq!(
+            new_lines(&previous, &lines(&["a", "b", "c", "d", "spin 2"])),
+            vec![2, 3, 4]
+        );
+        // Content that scrolled up out of the frame isn't new either
+        assert_eq!(new_lines(&previous, &lines(&["b", "x", "spin 1"])), vec![1]);
+        assert_eq!(new_lines(&[], &lines(&["a"])), vec![0]);
+    }
+
+    #[test]
+    fn test_repeated_frames_are_stored_once() {
+        let mut dedup = FrameDedup::new();
+        let frame = b"\x1b[?2026h\x1b[2K\x1b[1mHello\x1b[0m\r\n\x1b[2Kworld\r\n\x1b[?2026l";
+        assert_eq!(
+            push(&mut dedup, frame),
+            "\x1b[0;1mHello\x1b[0m\r\nworld\r\n"
+        );
+        assert_eq!(push(&mut dedup, frame), "");
+    }
+
+    #[test]
+    fn test_only_changed_and_new_lines_are_stored() {
+        let mut dedup = FrameDedup::new();
+        push(&mut dedup, b"intro\r\n\xe2\xa0\x8b working\r\n");
+        let stored = push(
+            &mut dedup,
+            b"\x1b[2A\rintro\r\nanswer line\r\n\xe2\xa0\x99 working\r\n",
+        );
+        assert_eq!(stored, "answer line\r\n\u{2819} working\r\n");
+    }
+
+    #[test]
+    fn test_reset_stores_everything_again() {
+        let mut dedup = FrameDedup::new();
+        push(&mut dedup, b"same\r\n");
+        dedup.reset();
+        assert_eq!(push(&mut dedup, b"same\r\n"), "same\r\n");
+    }
+
+    #[test]
+    fn test_alternate_screen_block_is_rejected() {
+        let mut dedup = FrameDedup::new();
+        let mut output = Vec::new();
+        assert!(!dedup.push_frame(b"\x1b[?1049hvim", 5, 40, &mut output));
+        assert!(output.is_empty());
+    }
+}
@@ -6,7 +6,11 @@
 //!
 //! IMPORTANT: Every enum variant must be explicitly classified.
 //! No catch-all fallbacks - we must consciously decide on each case.
+//!
+//! The same classification picks out side-channel sequences (titles,
+//! notifications and the like) for forwarding to the terminal.
 
+use crate::side_channel::SideChannel;
 use std::fmt::Write as FmtWrite;
 use termwiz::escape::Action;
 use termwiz::escape::csi::CSI;
@@ -32,10 +36,21 @@ impl HistoryFilter {
 
     /// Filter bytes, returning only safe sequences for history.
     pub fn filter(&mut self, input: &[u8]) -> Vec<u8> {
+        use termwiz::escape::{Esc, EscCode};
+
         let actions = self.parser.parse_as_vec(input);
         let mut output = String::new();
 
+        let mut after_osc = false;
         for action in actions {
+            // The parser passes on the ST ending an OSC as an action of its
+            // own, but the re-encoded OSC already ends with one
+            let is_terminator = matches!(action, Action::Esc(Esc::Code(EscCode::StringTerminator)));
+            if is_terminator && after_osc {
+                after_osc = false;
+                continue;
+            }
+            after_osc = matches!(action, Action::OperatingSystemCommand(_));
             if is_safe_for_history(&action) {
                 // Re-encode the action
                 let _ = write!(output, "{}", action);
@@ -84,7 +99,7 @@ fn classify_action(action: &Action) -> Classification {
         Action::Esc(esc) => classify_esc(esc),
 
         // === MIXED: OSC commands - check for queries ===
-        Action::OperatingSystemCommand(osc) => classify_osc(osc),
+        Action::OperatingSystemCommand(osc) => classify_osc(osc).0,
 
         // === BLACKLIST: Device control (DCS) - queries and modes ===
         Action::DeviceControl(_) => Blacklist,
@@ -352,42 +367,51 @@ fn classify_esc(esc: &termwiz::escape::Esc) -> Classification {
     }
 }
 
-/// Classify OSC commands - every variant explicitly handled.
-fn classify_osc(osc: &termwiz::escape::OperatingSystemCommand) -> Classification {
+/// Classify OSC commands - every variant explicitly handled. Also says
+/// which side channel the command belongs to, if it is one the proxy can
+/// forward.
+fn classify_osc(
+    osc: &termwiz::escape::OperatingSystemCommand,
+) -> (Classification, Option<SideChannel>) {
     use Classification::*;
     use termwiz::escape::osc::OperatingSystemCommand;
 
     match osc {
-        // === WHITELIST: Title setting ===
-        OperatingSystemCommand::SetIconNameAndWindowTitle(_) => Whitelist,
-        OperatingSystemCommand::SetIconName(_) => Whitelist,
-        OperatingSystemCommand::SetIconNameSun(_) => Whitelist,
-        OperatingSystemCommand::SetWindowTitle(_) => Whitelist,
-        OperatingSystemCommand::SetWindowTitleSun(_) => Whitelist,
+        // === WHITELIST: Title setting (OSC 0/1/2) ===
+        OperatingSystemCommand::SetIconNameAndWindowTitle(_) => {
+            (Whitelist, Some(SideChannel::Title))
+        }
+        OperatingSystemCommand::SetIconName(_) => (Whitelist, Some(SideChannel::Title)),
+        OperatingSystemCommand::SetIconNameSun(_) => (Whitelist, Some(SideChannel::Title)),
+        OperatingSystemCommand::SetWindowTitle(_) => (Whitelist, Some(SideChannel::Title)),
+        OperatingSystemCommand::SetWindowTitleSun(_) => (Whitelist, Some(SideChannel::Title)),
 
-        // === WHITELIST: Hyperlinks ===
-        OperatingSystemCommand::SetHyperlink(_) => Whitelist,
+        // === WHITELIST: Hyperlinks, drawn by the renderer ===
+        OperatingSystemCommand::SetHyperlink(_) => (Whitelist, None),
 
         // === WHITELIST: Color palette setting ===
-        OperatingSystemCommand::ChangeColorNumber(_) => Whitelist,
+        OperatingSystemCommand::ChangeColorNumber(_) => (Whitelist, None),
 
         // === WHITELIST: Reset colors ===
-        OperatingSystemCommand::ResetColors(_) => Whitelist,
+        OperatingSystemCommand::ResetColors(_) => (Whitelist, None),
 
         // === WHITELIST: Reset dynamic color ===
-        OperatingSystemCommand::ResetDynamicColor(_) => Whitelist,
+        OperatingSystemCommand::ResetDynamicColor(_) => (Whitelist, None),
 
-        // === WHITELIST: Selection setting (not query) ===
-        OperatingSystemCommand::SetSelection(_, _) => Whitelist,
+        // === WHITELIST: Selection setting (OSC 52, not query) ===
+        OperatingSystemCommand::SetSelection(_, _) => (Whitelist, Some(SideChannel::Clipboard)),
 
-        // === WHITELIST: System notification ===
-        OperatingSystemCommand::SystemNotification(_) => Whitelist,
+        // === WHITELIST: System notification (OSC 9) ===
+        OperatingSystemCommand::SystemNotification(_) => {
+            (Whitelist, Some(SideChannel::Notification))
+        }
 
         // === BLACKLIST: Selection clear ===
-        OperatingSystemCommand::ClearSelection(_) => Blacklist,
+        OperatingSystemCommand::ClearSelection(_) => (Blacklist, Some(SideChannel::Clipboard)),
 
-        // === BLACKLIST: Selection query ===
-        OperatingSystemCommand::QuerySelection(_) => Blacklist,
+        // === BLACKLIST: Selection query, never forwarded since the
+        // response would arrive on stdin ===
+        OperatingSystemCommand::QuerySelection(_) => (Blacklist, None),
 
         // === MIXED: Dynamic colors (check for query) ===
         OperatingSystemCommand::ChangeDynamicColors(_, colors) => {
@@ -396,29 +420,46 @@ fn classify_osc(osc: &termwiz::escape::OperatingSystemCommand) -> Classification
                 .iter()
                 .all(|c| matches!(c, termwiz::escape::osc::ColorOrQuery::Color(_)))
             {
-                Whitelist
+                (Whitelist, None)
             } else {
-                Blacklist
+                (Blacklist, None)
             }
         }
 
         // === MIXED: iTerm proprietary ===
-        OperatingSystemCommand::ITermProprietary(iterm) => classify_iterm(iterm),
+        OperatingSystemCommand::ITermProprietary(iterm) => (classify_iterm(iterm), None),
 
         // === WHITELIST: FinalTerm semantic prompts ===
-        OperatingSystemCommand::FinalTermSemanticPrompt(_) => Whitelist,
+        OperatingSystemCommand::FinalTermSemanticPrompt(_) => (Whitelist, None),
 
-        // === BLACKLIST: Current directory (pwd) - could leak info ===
-        OperatingSystemCommand::CurrentWorkingDirectory(_) => Blacklist,
+        // === BLACKLIST: Current directory (OSC 7) - could leak info ===
+        OperatingSystemCommand::CurrentWorkingDirectory(_) => (Blacklist, Some(SideChannel::Cwd)),
 
-        // === BLACKLIST: Rxvt extension ===
-        OperatingSystemCommand::RxvtExtension(_) => Blacklist,
+        // === BLACKLIST: Rxvt extension, but OSC 777;notify is a
+        // notification ===
+        OperatingSystemCommand::RxvtExtension(params) => (
+            Blacklist,
+            params
+                .first()
+                .is_some_and(|p| p == "notify")
+                .then_some(SideChannel::Notification),
+        ),
 
         // === BLACKLIST: ConEmu progress ===
-        OperatingSystemCommand::ConEmuProgress(_) => Blacklist,
+        OperatingSystemCommand::ConEmuProgress(_) => (Blacklist, None),
 
         // === BLACKLIST: Unspecified OSC ===
-        OperatingSystemCommand::Unspecified(_) => Blacklist,
+        OperatingSystemCommand::Unspecified(_) => (Blacklist, None),
+    }
+}
+
+/// Which side channel an action belongs to, if it is one the proxy can
+/// forward.
+pub fn classify_side_channel(action: &Action) -> Option<SideChannel> {
+    match action {
+        Action::Control(termwiz::escape::ControlCode::Bell) => Some(SideChannel::Bell),
+        Action::OperatingSystemCommand(osc) => classify_osc(osc).1,
+        _ => None,
     }
 }
 
@@ -577,4 +618,23 @@ mod tests {
         let output = filter.filter(input);
         assert!(output.is_empty(), "OSC query should be filtered");
     }
+
+    #[test]
+    fn test_hyperlink_passes() {
+        let mut filter = HistoryFilter::new();
+        let output = filter.filter(b"\x1b]8;;http://example.com\x07link\x1b]8;;\x07");
+        let output_str = String::from_utf8_lossy(&output);
+        assert!(output_str.contains("8;;http://example.com"));
+        assert!(output_str.contains("link"));
+    }
+
+    #[test]
+    fn test_osc_terminator_not_doubled() {
+        let mut filter = HistoryFilter::new();
+        let output = filter.filter(b"\x1b]8;;http://example.com\x1b\\link\x1b]8;;\x1b\\\x1b\\");
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "\x1b]8;;http://example.com\x1b\\link\x1b]8;;\x1b\\\x1b\\"
+        );
+    }
 }
@@ -0,0 +1,216 @@
+//! Earlier generations of history.
+//!
+//! A full redraw starts a new generation of history. Instead of discarding
+//! the old one, it is retired into a bounded ring so lookback can still show
+//! what was on screen before the redraw.
+
+use crate::escape_sequences::{CLEAR_SCREEN, CLEAR_SCROLLBACK, CURSOR_HOME};
+use crate::line_buffer::LineBuffer;
+use std::collections::VecDeque;
+
+const SEPARATOR: &[u8] = b"\r\n\x1b[7m--- full redraw ---\x1b[0m\r\n";
+
+/// Which part of history lookback shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryView {
+    /// A single generation, counted back from the current one (0)
+    Generation(usize),
+    /// All generations joined, oldest first
+    Joined,
+}
+
+impl Default for HistoryView {
+    fn default() -> Self {
+        HistoryView::Generation(0)
+    }
+}
+
+pub struct HistoryGenerations {
+    previous: VecDeque<LineBuffer>,
+    max_generations: usize,
+}
+
+impl HistoryGenerations {
+    pub fn new(max_generations: usize) -> Self {
+        Self {
+            previous: VecDeque::new(),
+            max_generations,
+        }
+    }
+
+    /// Keep a finished generation, dropping the oldest beyond the limit.
+    /// Retired generations are only read back, so they are kept compressed.
+    pub fn retire(&mut self, mut generation: LineBuffer) {
+        if self.max_generations == 0 {
+            return;
+        }
+        generation.compact();
+        self.previous.push_back(generation);
+        while self.previous.len() > self.max_generations {
+            self.previous.pop_front();
+        }
+    }
+
+    /// Number of generations including the current one.
+    pub fn count(&self) -> usize {
+        self.previous.len() + 1
+    }
+
+    /// One step older, stopping at the oldest generation.
+    pub fn older(&self, view: HistoryView) -> HistoryView {
+        match view {
+            HistoryView::Generation(back) => {
+                HistoryView::Generation((back + 1).min(self.previous.len()))
+            }
+            HistoryView::Joined => HistoryView::Generation(self.previous.len()),
+        }
+    }
+
+    /// One step newer, stopping at the current generation.
+    pub fn newer(&self, view: HistoryView) -> HistoryView {
+        match view {
+            HistoryView::Generation(back) => HistoryView::Generation(back.saturating_sub(1)),
+            HistoryView::Joined => HistoryView::Generation(0),
+        }
+    }
+
+    /// Short description of the view for status lines, e.g. "2/3".
+    pub fn label(&self, view: HistoryView) -> String {
+        match view {
+            HistoryView::Generation(back) => format!("{}/{}", self.count() - back, self.count()),
+            HistoryView::Joined => format!("all {}", self.count()),
+        }
+    }
+
+    /// Append the history for `view`, with `current` as the live generation.
+    ///
+    /// Joined generations have the clears they start with removed so earlier
+    /// generations aren't wiped on replay, and are separated by a marker
+    /// line.
+    pub fn append_view(&self, view: HistoryView, current: &LineBuffer, output: &mut Vec<u8>) {
+        match view {
+            HistoryView::Generation(0) => current.append_all(output),
+            HistoryView::Generation(back) => {
+                let index = self.previous.len().saturating_sub(back);
+                match self.previous.get(index) {
+                    Some(generation) => generation.append_all(output),
+                    None => current.append_all(output),
+                }
+            }
+            HistoryView::Joined => {
+                output.extend_from_slice(CLEAR_SCREEN);
+                output.extend_from_slice(CURSOR_HOME);
+                let mut buffer = Vec::new();
+                for (index, generation) in self.previous.iter().chain([current]).enumerate() {
+                    if index > 0 {
+                        output.extend_from_slice(SEPARATOR);
+                    }
+                    buffer.clear();
+                    generation.append_all(&mut buffer);
+                    output.extend_from_slice(strip_leading_clears(&buffer));
+                }
+            }
+        }
+    }
+}
+
+/// `data` without the screen clears and cursor home it starts with. Later
+/// cursor homes are part of what was drawn and are kept.
+fn strip_leading_clears(mut data: &[u8]) -> &[u8] {
+    while let Some(seq) = [CLEAR_SCREEN, CLEAR_SCROLLBACK, CURSOR_HOME]
+        .iter()
+        .find(|seq| data.starts_with(seq))
+    {
+        data = &data[seq.len()..];
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(content: &[u8]) -> LineBuffer {
+        let mut buffer = LineBuffer::new(100);
+        buffer.push_bytes(content);
+        buffer
+    }
+
+    fn view(generations: &HistoryGenerations, view: HistoryView, current: &LineBuffer) -> Vec<u8> {
+        let mut output = Vec::new();
+        generations.append_view(view, current, &mut output);
+        output
+    }
+
+    #[test]
+    fn test_retire_is_bounded() {
+        let mut generations = HistoryGenerations::new(2);
+        generations.retire(buffer(b"one\n"));
+        generations.retire(buffer(b"two\n"));
+        generations.retire(buffer(b"three\n"));
+        assert_eq!(generations.count(), 3);
+
+        let current = buffer(b"four\n");
+        assert_eq!(
+            view(&generations, HistoryView::Generation(0), &current),
+            b"four\n"
+        );
+        assert_eq!(
+            view(&generations, HistoryView::Generation(1), &current),
+            b"three\n"
+        );
+        assert_eq!(
+            view(&generations, HistoryView::Generation(2), &current),
+            b"two\n"
+        );
+    }
+
+    #[test]
+    fn test_disabled_keeps_nothing() {
+        let mut generations = HistoryGenerations::new(0);
+        generations.retire(buffer(b"one\n"));
+        assert_eq!(generations.count(), 1);
+    }
+
+    #[test]
+    fn test_step_older_and_newer() {
+        let mut generations = HistoryGenerations::new(5);
+        generations.retire(buffer(b"one\n"));
+        generations.retire(buffer(b"two\n"));
+
+        let view = generations.older(HistoryView::default());
+        assert_eq!(view, HistoryView::Generation(1));
+        assert_eq!(generations.label(view), "2/3");
+        let view = generations.older(generations.older(view));
+        assert_eq!(view, HistoryView::Generation(2));
+        assert_eq!(generations.label(view), "1/3");
+        assert_eq!(generations.newer(view), HistoryView::Generation(1));
+        assert_eq!(
+            generations.newer(HistoryView::Generation(0)),
+            HistoryView::Generation(0)
+        );
+        assert_eq!(generations.label(HistoryView::Joined), "all 3");
+    }
+
+    #[test]
+    fn test_joined_strips_clears() {
+        let mut generations = HistoryGenerations::new(5);
+        generations.retire(buffer(b"\x1b[2J\x1b[Hone\n"));
+        let current = buffer(b"\x1b[2J\x1b[3J\x1b[H\x1b[1mtwo\x1b[0m\n");
+        let mut expected = b"\x1b[2J\x1b[Hone\n".to_vec();
+        expected.extend_from_slice(SEPARATOR);
+        expected.extend_from_slice(b"\x1b[1mtwo\x1b[0m\n");
+        assert_eq!(view(&generations, HistoryView::Joined, &current), expected);
+    }
+
+    #[test]
+    fn test_joined_keeps_later_cursor_homes() {
+        let mut generations = HistoryGenerations::new(5);
+        generations.retire(buffer(b"\x1b[2J\x1b[Hone\x1b[Htop\n"));
+        let current = buffer(b"\x1b[2J\x1b[Htwo\x1b[2J\n");
+        let mut expected = b"\x1b[2J\x1b[Hone\x1b[Htop\n".to_vec();
+        expected.extend_from_slice(SEPARATOR);
+        expected.extend_from_slice(b"two\x1b[2J\n");
+        assert_eq!(view(&generations, HistoryView::Joined, &current), expected);
+    }
+}
@@ -0,0 +1,391 @@
+//! OSC 8 hyperlinks on the rendered screen.
+//!
+//! `vt100` cells carry no link, so links are tracked here beside the
+//! emulator. Output is split at OSC 8 sequences; whatever is written while a
+//! link is open marks the cells between the cursor positions before and
+//! after it. Marks remember the text they cover, so they can follow it as
+//! the screen scrolls and are dropped once it is overwritten. Rendering
+//! draws the linked runs again, wrapped in OSC 8, on top of the emulator's
+//! own output.
+
+use crate::screen_history::write_cells;
+use memchr::memmem;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+const OSC8_START: &[u8] = b"\x1b]8;";
+const OSC8_CLOSE: &[u8] = b"\x1b]8;;\x1b\\";
+
+/// Longest OSC 8 sequence held back while waiting for the rest of it.
+const MAX_PENDING_LEN: usize = 4096;
+
+/// Output, or an OSC 8 sequence opening a link (its `params;URI`) or
+/// closing one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Output(Cow<'a, [u8]>),
+    Link(Option<Rc<str>>),
+}
+
+#[derive(Debug, Clone)]
+struct LinkedCell {
+    link: Rc<str>,
+    contents: String,
+}
+
+/// Cells of one row inside a link, which is its `params;URI`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkRun {
+    pub row: u16,
+    pub cols: Range<u16>,
+    pub link: Rc<str>,
+}
+
+#[derive(Debug, Default)]
+pub struct Hyperlinks {
+    /// Link the output is inside of
+    open: Option<Rc<str>>,
+    cells: BTreeMap<(u16, u16), LinkedCell>,
+    /// Runs the terminal was last sent with their links
+    rendered: Vec<LinkRun>,
+    /// Start of an OSC 8 sequence cut off at the end of the last output
+    pending: Vec<u8>,
+}
+
+impl Hyperlinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split output at OSC 8 sequences.
+    pub fn split<'a>(&mut self, data: &'a [u8]) -> Vec<Piece<'a>> {
+        if self.pending.is_empty()
+            && memmem::find(data, OSC8_START).is_none()
+            && partial_start_len(data) == 0
+        {
+            return vec![Piece::Output(Cow::Borrowed(data))];
+        }
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(data);
+
+        let mut pieces = Vec::new();
+        let mut rest = buf.as_slice();
+        loop {
+            let Some(start) = memmem::find(rest, OSC8_START) else {
+                let keep = partial_start_len(rest);
+                push_output(&mut pieces, &rest[..rest.len() - keep]);
+                self.pending = rest[rest.len() - keep..].to_vec();
+                break;
+            };
+            push_output(&mut pieces, &rest[..start]);
+            let body = &rest[start + OSC8_START.len()..];
+            let Some((end, terminator_len)) = find_terminator(body) else {
+                if rest.len() - start <= MAX_PENDING_LEN {
+                    self.pending = rest[start..].to_vec();
+                } else {
+                    // Too long to be a link; let the emulator have it
+                    push_output(&mut pieces, &rest[start..]);
+                }
+                break;
+            };
+            let link = String::from_utf8_lossy(&body[..end]);
+            // `params;URI`, closing the link when the URI is empty
+            let opens = link.split_once(';').is_some_and(|(_, uri)| !uri.is_empty());
+            pieces.push(Piece::Link(opens.then(|| Rc::from(link.as_ref()))));
+            rest = &body[end + terminator_len..];
+        }
+        pieces
+    }
+
+    pub fn set_link(&mut self, link: Option<Rc<str>>) {
+        self.open = link;
+    }
+
+    /// Record the cells written since the cursor was at `from` as inside
+    /// the open link, or as outside any link.
+    pub fn mark(&mut self, screen: &vt100::Screen, from: (u16, u16)) {
+        if screen.alternate_screen() {
+            return;
+        }
+        self.follow_scrolling(screen);
+        let to = screen.cursor_position();
+        if to <= from {
+            return;
+        }
+        let (_, cols) = screen.size();
+        for row in from.0..=to.0 {
+            let start = if row == from.0 { from.1 } else { 0 };
+            let end = if row == to.0 { to.1 } else { cols };
+            for col in start..end.min(cols) {
+                match (&self.open, screen.cell(row, col)) {
+                    (Some(link), Some(cell)) => {
+                        self.cells.insert(
+                            (row, col),
+                            LinkedCell {
+                                link: link.clone(),
+                                contents: cell.contents().to_string(),
+                            },
+                        );
+                    }
+                    _ => {
+                        self.cells.remove(&(row, col));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw linked runs the terminal doesn't have with their links, and
+    /// runs that lost their link without one. `found` adds links for text
+    /// the child didn't link itself. After a full render every run is drawn.
+    /// Returns whether anything was written.
+    pub fn render(
+        &mut self,
+        screen: &vt100::Screen,
+        full: bool,
+        found: Vec<LinkRun>,
+        output: &mut Vec<u8>,
+    ) -> bool {
+        self.follow_scrolling(screen);
+        let mut runs = self.runs();
+        let own = runs.len();
+        for run in found {
+            let overlaps = runs[..own].iter().any(|r| {
+                r.row == run.row && r.cols.start < run.cols.end && run.cols.start < r.cols.end
+            });
+            if !overlaps {
+                runs.push(run);
+            }
+        }
+        if full {
+            self.rendered.clear();
+        }
+        let start_len = output.len();
+        for run in self.rendered.iter().filter(|r| !runs.contains(r)) {
+            write_run(screen, run, false, output);
+        }
+        for run in runs.iter().filter(|r| !self.rendered.contains(r)) {
+            write_run(screen, run, true, output);
+        }
+        self.rendered = runs;
+        output.len() > start_len
+    }
+
+    /// Move each linked run whose text is no longer where it was to the
+    /// nearest row above that has the same text in the same columns, which
+    /// is where scrolling takes it, and drop it if there is none.
+    fn follow_scrolling(&mut self, screen: &vt100::Screen) {
+        let mut cells = BTreeMap::new();
+        for run in self.runs() {
+            let has_run = |row: u16| {
+                run.cols.clone().all(|col| {
+                    screen
+                        .cell(row, col)
+                        .is_some_and(|cell| cell.contents() == self.cells[&(run.row, col)].contents)
+                })
+            };
+            let Some(row) = (0..=run.row).rev().find(|&row| has_run(row)) else {
+                continue;
+            };
+            for col in run.cols {
+                cells.insert((row, col), self.cells[&(run.row, col)].clone());
+            }
+        }
+        self.cells = cells;
+    }
+
+    fn runs(&self) -> Vec<LinkRun> {
+        let mut runs: Vec<LinkRun> = Vec::new();
+        for (&(row, col), linked) in &self.cells {
+            match runs.last_mut() {
+                Some(run) if run.row == row && run.cols.end == col && run.link == linked.link => {
+                    run.cols.end += 1;
+                }
+                _ => runs.push(LinkRun {
+                    row,
+                    cols: col..col + 1,
+                    link: linked.link.clone(),
+                }),
+            }
+        }
+        runs
+    }
+}
+
+fn push_output<'a>(pieces: &mut Vec<Piece<'a>>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        pieces.push(Piece::Output(Cow::Owned(bytes.to_vec())));
+    }
+}
+
+/// Position and length of the BEL or ST ending an OSC.
+fn find_terminator(body: &[u8]) -> Option<(usize, usize)> {
+    body.iter().enumerate().find_map(|(i, &b)| match b {
+        0x07 => Some((i, 1)),
+        0x1b if body.get(i + 1) == Some(&b'\\') => Some((i, 2)),
+        _ => None,
+    })
+}
+
+/// Length of the longest end of `data` that could start an OSC 8 sequence.
+fn partial_start_len(data: &[u8]) -> usize {
+    (1..OSC8_START.len())
+        .rev()
+        .find(|&len| data.ends_with(&OSC8_START[..len]))
+        .unwrap_or(0)
+}
+
+fn write_run(screen: &vt100::Screen, run: &LinkRun, linked: bool, output: &mut Vec<u8>) {
+    output.extend_from_slice(
+        format!("\x1b[{};{}H\x1b[m", run.row + 1, run.cols.start + 1).as_bytes(),
+    );
+    if linked {
+        output.extend_from_slice(OSC8_START);
+        output.extend_from_slice(run.link.as_bytes());
+        output.extend_from_slice(b"\x1b\\");
+    }
+    write_cells(screen, run.row, run.cols.clone(), output);
+    if linked {
+        output.extend_from_slice(OSC8_CLOSE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed output the way the proxy does.
+    fn feed(links: &mut Hyperlinks, parser: &mut vt100::Parser, data: &[u8]) {
+        for piece in links.split(data) {
+            match piece {
+                Piece::Output(bytes) => {
+                    let from = parser.screen().cursor_position();
+                    parser.process(&bytes);
+                    links.mark(parser.screen(), from);
+                }
+                Piece::Link(link) => links.set_link(link),
+            }
+        }
+    }
+
+    fn render(links: &mut Hyperlinks, parser: &vt100::Parser, full: bool) -> String {
+        let mut output = Vec::new();
+        links.render(parser.screen(), full, Vec::new(), &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_split() {
+        let mut links = Hyperlinks::new();
+        assert_eq!(
+            links.split(b"plain"),
+            vec![Piece::Output(Cow::Borrowed(&b"plain"[..]))]
+        );
+        let pieces = links.split(b"a\x1b]8;;http://x\x1b\\b\x1b]8;;\x07c\x1b]");
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Output(Cow::Owned(b"a".to_vec())),
+                Piece::Link(Some(Rc::from(";http://x"))),
+                Piece::Output(Cow::Owned(b"b".to_vec())),
+                Piece::Link(None),
+                Piece::Output(Cow::Owned(b"c".to_vec())),
+            ]
+        );
+        // The held back `ESC ]` turns out not to be a link
+        assert_eq!(
+            links.split(b"0;title\x07"),
+            vec![Piece::Output(Cow::Owned(b"\x1b]0;title\x07".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn test_link_split_across_output() {
+        let mut links = Hyperlinks::new();
+        assert!(links.split(b"\x1b]8;id=1;file:///tmp/").is_empty());
+        assert_eq!(
+            links.split(b"a.rs\x1b\\"),
+            vec![Piece::Link(Some(Rc::from("id=1;file:///tmp/a.rs")))]
+        );
+    }
+
+    #[test]
+    fn test_renders_linked_runs() {
+        let mut links = Hyperlinks::new();
+        let mut parser = vt100::Parser::new(5, 20, 0);
+        feed(
+            &mut links,
+            &mut parser,
+            b"see \x1b]8;;http://x\x1b\\\x1b[4mdocs\x1b[m\x1b]8;;\x1b\\ here",
+        );
+        let output = render(&mut links, &parser, true);
+        assert_eq!(
+            output,
+            "\x1b[1;5H\x1b[m\x1b]8;;http://x\x1b\\\x1b[0;4mdocs\x1b[0m\x1b]8;;\x1b\\"
+        );
+        // Nothing changed, so a diff render draws nothing
+        assert_eq!(render(&mut links, &parser, false), "");
+        assert_eq!(render(&mut links, &parser, true), output);
+    }
+
+    #[test]
+    fn test_overwritten_link_is_dropped() {
+        let mut links = Hyperlinks::new();
+        let mut parser = vt100::Parser::new(5, 20, 0);
+        feed(
+            &mut links,
+            &mut parser,
+            b"\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\",
+        );
+        render(&mut links, &parser, true);
+        // Rewritten with different text: drawn without the link
+        feed(&mut links, &mut parser, b"\rnope");
+        assert_eq!(render(&mut links, &parser, false), "\x1b[1;1H\x1b[mnope");
+        assert_eq!(render(&mut links, &parser, false), "");
+        // Rewritten with the same text outside a link
+        feed(
+            &mut links,
+            &mut parser,
+            b"\r\x1b]8;;http://x\x1b\\nope\x1b]8;;\x1b\\",
+        );
+        assert!(render(&mut links, &parser, false).contains("http://x"));
+        feed(&mut links, &mut parser, b"\r");
+        feed(&mut links, &mut parser, b"nope");
+        assert_eq!(render(&mut links, &parser, false), "\x1b[1;1H\x1b[mnope");
+    }
+
+    #[test]
+    fn test_link_wrapping_onto_next_row() {
+        let mut links = Hyperlinks::new();
+        let mut parser = vt100::Parser::new(5, 10, 0);
+        feed(
+            &mut links,
+            &mut parser,
+            b"12345678\x1b]8;;u:x\x1b\\abcd\x1b]8;;\x1b\\",
+        );
+        let output = render(&mut links, &parser, true);
+        assert!(output.contains("\x1b[1;9H\x1b[m\x1b]8;;u:x\x1b\\ab"));
+        assert!(output.contains("\x1b[2;1H\x1b[m\x1b]8;;u:x\x1b\\cd"));
+    }
+
+    #[test]
+    fn test_link_follows_scrolling() {
+        let mut links = Hyperlinks::new();
+        let mut parser = vt100::Parser::new(3, 20, 0);
+        feed(
+            &mut links,
+            &mut parser,
+            b"top\r\n\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\\r\n",
+        );
+        render(&mut links, &parser, true);
+        feed(&mut links, &mut parser, b"one\r\n");
+        let output = render(&mut links, &parser, false);
+        assert!(output.contains("\x1b[1;1H\x1b[m\x1b]8;;http://x\x1b\\link"));
+        // Scrolled off the top
+        feed(&mut links, &mut parser, b"two\r\n");
+        render(&mut links, &parser, false);
+        assert!(links.cells.is_empty());
+    }
+}
@@ -0,0 +1,643 @@
+//! Splitting stdin into key presses.
+//!
+//! Keys are read by termwiz's `InputParser`, the same parser the pager
+//! uses, and turned into a `KeyCombination`, so hotkeys match the same way
+//! whether the terminal sends legacy bytes, xterm modifier sequences or
+//! Kitty `CSI u` sequences. Each key keeps the raw bytes it arrived as,
+//! which is what gets passed through to the child for anything that isn't
+//! a hotkey, so input is fed to the parser a byte at a time.
+//!
+//! The parser doesn't know the Kitty protocol's extra modifiers, lock
+//! modifiers, event types or functional keys, nor X10 mouse reports, so
+//! CSI sequences are read whole before it sees them. Kitty modifiers are
+//! taken off and added to what the parser makes of the rest, and X10 mouse
+//! reports are passed on without it.
+//!
+//! Bracketed pastes are not split into keys: everything from the start
+//! marker to the end marker comes out as one paste input.
+
+use crate::escape_sequences::BRACKETED_PASTE_START;
+use crate::key_parser::{KITTY_F13, KeyCode, KeyCombination, Modifiers};
+use std::time::{Duration, Instant};
+use termwiz::input::{self, InputEvent, InputParser, KeyEvent};
+
+/// How long a lone ESC (or an unfinished escape sequence) waits for the rest
+/// of the sequence before it is taken as typed.
+pub const ESCAPE_TIMEOUT_MS: u64 = 50;
+
+/// Kitty key codes for functional keys without a legacy encoding live in
+/// this private use range. Only F13 to F24 are decoded.
+const KITTY_FUNCTIONAL_KEYS: std::ops::RangeInclusive<u32> = 57344..=63743;
+
+/// Kitty event type of a key press; repeats are 2 and releases 3.
+const KITTY_EVENT_PRESS: u32 = 1;
+
+/// An X10 mouse report is this followed by three raw bytes.
+const X10_MOUSE: &[u8] = b"\x1b[M";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyInput {
+    pub bytes: Vec<u8>,
+    /// The key pressed, if the bytes are a key press that can be decoded
+    pub key: Option<KeyCombination>,
+    /// A bracketed paste, markers included
+    pub paste: bool,
+}
+
+impl KeyInput {
+    /// Whether the key came in a legacy encoding, which can't tell every
+    /// key apart, rather than as a Kitty `CSI u` sequence.
+    pub fn is_legacy(&self) -> bool {
+        !(self.bytes.starts_with(b"\x1b[") && self.bytes.ends_with(b"u"))
+    }
+
+    fn paste(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            key: None,
+            paste: true,
+        }
+    }
+}
+
+/// What a whole CSI sequence is, before the parser reads it.
+enum CsiKey {
+    /// A key the parser has no sequence for
+    Key(KeyCombination),
+    /// A sequence for the parser to read, and modifiers to add to its key
+    Parse(Vec<u8>, Modifiers),
+    /// Not a key press
+    None,
+}
+
+pub struct InputDecoder {
+    parser: InputParser,
+    /// Bytes given to the parser that it hasn't made an event of yet
+    fed: Vec<u8>,
+    /// A lone ESC or CSI sequence being read, not yet given to the parser
+    csi: Vec<u8>,
+    /// Raw bytes still to come of an X10 mouse report
+    mouse_bytes: usize,
+    in_paste: bool,
+    pending_since: Option<Instant>,
+}
+
+impl Default for InputDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputDecoder {
+    pub fn new() -> Self {
+        Self {
+            parser: InputParser::new(),
+            fed: Vec::new(),
+            csi: Vec::new(),
+            mouse_bytes: 0,
+            in_paste: false,
+            pending_since: None,
+        }
+    }
+
+    /// Split `data`, after anything held back from earlier reads, into keys.
+    /// An unfinished escape sequence or paste at the end is held back until
+    /// more input arrives, or, short of a paste, it times out.
+    pub fn decode(&mut self, data: &[u8]) -> Vec<KeyInput> {
+        let mut keys = Vec::new();
+        for &byte in data {
+            self.push(byte, &mut keys);
+        }
+        let pending = !self.fed.is_empty() || !self.csi.is_empty();
+        self.pending_since = (pending && !self.in_paste).then(Instant::now);
+        keys
+    }
+
+    fn push(&mut self, byte: u8, keys: &mut Vec<KeyInput>) {
+        if self.mouse_bytes > 0 {
+            self.csi.push(byte);
+            self.mouse_bytes -= 1;
+            if self.mouse_bytes == 0 {
+                keys.push(KeyInput {
+                    bytes: std::mem::take(&mut self.csi),
+                    key: None,
+                    paste: false,
+                });
+            }
+            return;
+        }
+        // Whatever the parser is in the middle of is its to finish
+        if !self.fed.is_empty() {
+            return self.feed(&[byte], keys);
+        }
+        match (self.csi.as_slice(), byte) {
+            ([], 0x1b) | ([0x1b], b'[') => self.csi.push(byte),
+            ([], _) => self.feed(&[byte], keys),
+            ([0x1b], _) => {
+                let bytes = [0x1b, byte];
+                self.csi.clear();
+                self.feed(&bytes, keys);
+            }
+            (_, 0x20..=0x3f) => self.csi.push(byte),
+            (_, 0x40..=0x7e) => {
+                self.csi.push(byte);
+                let sequence = std::mem::take(&mut self.csi);
+                self.finish_csi(sequence, keys);
+            }
+            _ => {
+                // Not a CSI sequence after all: the prefix is a key by itself
+                let prefix = std::mem::take(&mut self.csi);
+                let key = self.decode_whole(&prefix);
+                keys.push(KeyInput {
+                    bytes: prefix,
+                    key,
+                    paste: false,
+                });
+                self.push(byte, keys);
+            }
+        }
+    }
+
+    fn finish_csi(&mut self, sequence: Vec<u8>, keys: &mut Vec<KeyInput>) {
+        if sequence == X10_MOUSE {
+            self.csi = sequence;
+            self.mouse_bytes = 3;
+            return;
+        }
+        if sequence == BRACKETED_PASTE_START {
+            return self.feed(&sequence, keys);
+        }
+        let key = match csi_key(&sequence) {
+            CsiKey::Key(key) => Some(key),
+            CsiKey::Parse(bytes, modifiers) => {
+                self.decode_whole(&bytes).map(|key| KeyCombination {
+                    modifiers: Modifiers::from_kitty_bits(
+                        key.modifiers.kitty_bits() | modifiers.kitty_bits(),
+                    ),
+                    ..key
+                })
+            }
+            CsiKey::None => None,
+        };
+        keys.push(KeyInput {
+            bytes: sequence,
+            key,
+            paste: false,
+        });
+    }
+
+    /// Give `bytes` to the parser, and whatever it makes of them and the
+    /// bytes it held back to `keys`.
+    fn feed(&mut self, bytes: &[u8], keys: &mut Vec<KeyInput>) {
+        self.fed.extend_from_slice(bytes);
+        let events = self.parser.parse_as_vec(bytes, true);
+        // An ESC before the paste is let go as the paste starts
+        let starts_paste = !self.in_paste && self.fed.ends_with(BRACKETED_PASTE_START);
+        self.in_paste |= starts_paste;
+        if events.is_empty() {
+            return;
+        }
+        let keep = if starts_paste {
+            BRACKETED_PASTE_START.len()
+        } else {
+            0
+        };
+        let bytes = self.fed.drain(..self.fed.len() - keep).collect();
+        keys.push(self.key_input(bytes, events));
+    }
+
+    fn key_input(&mut self, bytes: Vec<u8>, events: Vec<InputEvent>) -> KeyInput {
+        match events.as_slice() {
+            [InputEvent::Paste(_)] => {
+                self.in_paste = false;
+                KeyInput::paste(bytes)
+            }
+            [InputEvent::Key(event)] => KeyInput {
+                key: key_combination(event, &bytes),
+                bytes,
+                paste: false,
+            },
+            // Mouse reports, and bytes the parser could only make several
+            // keys of, such as an unknown escape sequence
+            _ => KeyInput {
+                bytes,
+                key: None,
+                paste: false,
+            },
+        }
+    }
+
+    /// The key `bytes` are by themselves, with nothing to follow.
+    fn decode_whole(&mut self, bytes: &[u8]) -> Option<KeyCombination> {
+        match self.parser.parse_as_vec(bytes, false).as_slice() {
+            [InputEvent::Key(event)] => key_combination(event, bytes),
+            _ => None,
+        }
+    }
+
+    /// Time until held-back input times out.
+    pub fn time_until_flush(&self) -> Option<Duration> {
+        self.pending_since
+            .map(|since| Duration::from_millis(ESCAPE_TIMEOUT_MS).saturating_sub(since.elapsed()))
+    }
+
+    /// Give up waiting for the rest of an escape sequence. A lone ESC is the
+    /// Escape key; a cut-off sequence is passed on as is.
+    pub fn flush(&mut self) -> Vec<KeyInput> {
+        self.pending_since = None;
+        if self.in_paste {
+            return Vec::new();
+        }
+        if !self.csi.is_empty() {
+            let bytes = std::mem::take(&mut self.csi);
+            let key = if self.mouse_bytes > 0 {
+                None
+            } else {
+                self.decode_whole(&bytes)
+            };
+            self.mouse_bytes = 0;
+            return vec![KeyInput {
+                bytes,
+                key,
+                paste: false,
+            }];
+        }
+        if self.fed.is_empty() {
+            return Vec::new();
+        }
+        let events = self.parser.parse_as_vec(&[], false);
+        if events.is_empty() {
+            // Invalid UTF-8 the parser would otherwise hold on to for good
+            self.parser = InputParser::new();
+        }
+        let bytes = std::mem::take(&mut self.fed);
+        vec![self.key_input(bytes, events)]
+    }
+
+    /// Take held-back input without decoding it. Whoever takes it also
+    /// takes the rest of a paste in progress.
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        self.pending_since = None;
+        self.in_paste = false;
+        self.mouse_bytes = 0;
+        let mut pending = std::mem::take(&mut self.csi);
+        if !self.fed.is_empty() {
+            pending.append(&mut self.fed);
+            self.parser = InputParser::new();
+        }
+        pending
+    }
+}
+
+/// Length of the longest end of `data` that `marker` starts with, short of
+/// the whole marker.
+pub(crate) fn partial_marker_len(data: &[u8], marker: &[u8]) -> usize {
+    (1..marker.len().min(data.len() + 1))
+        .rev()
+        .find(|&len| data.ends_with(&marker[..len]))
+        .unwrap_or(0)
+}
+
+fn key(code: KeyCode, modifiers: Modifiers) -> KeyCombination {
+    KeyCombination { code, modifiers }
+}
+
+/// Decode the bytes of one key. Returns None for anything that isn't a key
+/// press: mouse reports, focus events, key releases, and Kitty functional
+/// keys `KeyCode` has no name for.
+pub fn decode_key(bytes: &[u8]) -> Option<KeyCombination> {
+    let mut decoder = InputDecoder::new();
+    let mut keys = decoder.decode(bytes);
+    keys.extend(decoder.flush());
+    match keys.as_slice() {
+        [input] => input.key.clone(),
+        _ => None,
+    }
+}
+
+/// Sort out a whole CSI sequence the parser may not know. Kitty sequences
+/// are cut down to the legacy sequence of their key with no modifiers, and
+/// sequences that aren't keys are left for the parser to make what it can
+/// of.
+fn csi_key(sequence: &[u8]) -> CsiKey {
+    let (&final_byte, params) = sequence[2..]
+        .split_last()
+        .expect("CSI sequence has a final byte");
+    let Ok(params) = std::str::from_utf8(params) else {
+        return CsiKey::None;
+    };
+    let key_final = matches!(
+        final_byte,
+        b'u' | b'~' | b'A'..=b'D' | b'F' | b'H' | b'P'..=b'S' | b'Z'
+    );
+    // Private sequences such as SGR mouse reports
+    if !key_final || params.starts_with(['<', '=', '>', '?']) {
+        return CsiKey::Parse(sequence.to_vec(), Modifiers::default());
+    }
+    // Parameters are separated by ';', sub-parameters by ':'
+    let mut fields = params.split(';').map(|field| field.split(':'));
+    let number = fields
+        .next()
+        .and_then(|mut sub| sub.next())
+        .filter(|n| !n.is_empty())
+        .map(str::parse::<u32>)
+        .transpose();
+    let (modifier, event) = match fields.next() {
+        Some(mut sub) => {
+            let modifier = sub.next().filter(|m| !m.is_empty()).unwrap_or("1");
+            let event = sub.next().unwrap_or("1");
+            (modifier.parse::<u32>(), event.parse::<u32>())
+        }
+        None => (Ok(1), Ok(KITTY_EVENT_PRESS)),
+    };
+    let (Ok(number), Ok(Some(bits)), Ok(KITTY_EVENT_PRESS)) =
+        (number, modifier.map(|m| m.checked_sub(1)), event)
+    else {
+        return CsiKey::None;
+    };
+    let number = number.unwrap_or(1);
+    let modifiers = Modifiers::from_kitty_bits(bits);
+    let legacy = match final_byte {
+        b'u' => match number {
+            n if (KITTY_F13..KITTY_F13 + 12).contains(&n) => {
+                return CsiKey::Key(key(KeyCode::F((n - KITTY_F13 + 13) as u8), modifiers));
+            }
+            n if KITTY_FUNCTIONAL_KEYS.contains(&n) => return CsiKey::None,
+            0..=0x7f => format!("\x1b[{}u", number),
+            n => {
+                return match char::from_u32(n) {
+                    Some(c) => CsiKey::Key(key(KeyCode::Char(c), modifiers)),
+                    None => CsiKey::None,
+                };
+            }
+        },
+        b'~' => format!("\x1b[{}~", number),
+        b'P'..=b'S' => format!("\x1bO{}", final_byte as char),
+        _ => format!("\x1b[{}", final_byte as char),
+    };
+    CsiKey::Parse(legacy.into_bytes(), modifiers)
+}
+
+/// The key the parser read from `bytes`.
+fn key_combination(event: &KeyEvent, bytes: &[u8]) -> Option<KeyCombination> {
+    let modifiers = Modifiers {
+        shift: event.modifiers.contains(input::Modifiers::SHIFT),
+        alt: event.modifiers.contains(input::Modifiers::ALT),
+        ctrl: event.modifiers.contains(input::Modifiers::CTRL),
+        ..Modifiers::default()
+    };
+    let code = match (&event.key, bytes.last()) {
+        // The parser reads ^H and ^J as Backspace and Enter, which would
+        // make them the same keys as what those send
+        (input::KeyCode::Backspace, Some(0x08)) => return char_key('\x08', modifiers),
+        (input::KeyCode::Enter, Some(b'\n')) => return char_key('\n', modifiers),
+        (input::KeyCode::Char(c), _) => return char_key(*c, modifiers),
+        (input::KeyCode::Enter, _) => KeyCode::Enter,
+        (input::KeyCode::Tab, _) => KeyCode::Tab,
+        (input::KeyCode::Backspace, _) => KeyCode::Backspace,
+        (input::KeyCode::Escape, _) => KeyCode::Esc,
+        (input::KeyCode::Insert, _) => KeyCode::Insert,
+        (input::KeyCode::Delete, _) => KeyCode::Delete,
+        (input::KeyCode::Home, _) => KeyCode::Home,
+        (input::KeyCode::End, _) => KeyCode::End,
+        (input::KeyCode::PageUp, _) => KeyCode::PageUp,
+        (input::KeyCode::PageDown, _) => KeyCode::PageDown,
+        (input::KeyCode::UpArrow | input::KeyCode::ApplicationUpArrow, _) => KeyCode::Up,
+        (input::KeyCode::DownArrow | input::KeyCode::ApplicationDownArrow, _) => KeyCode::Down,
+        (input::KeyCode::LeftArrow | input::KeyCode::ApplicationLeftArrow, _) => KeyCode::Left,
+        (input::KeyCode::RightArrow | input::KeyCode::ApplicationRightArrow, _) => KeyCode::Right,
+        (input::KeyCode::Function(n), _) => KeyCode::F(*n),
+        _ => return None,
+    };
+    Some(key(code, modifiers))
+}
+
+/// The key for a character the parser read, which for control characters
+/// is the key that sends them.
+fn char_key(c: char, mut modifiers: Modifiers) -> Option<KeyCombination> {
+    let code = match c {
+        '\r' => KeyCode::Enter,
+        '\t' => KeyCode::Tab,
+        '\x7f' => KeyCode::Backspace,
+        '\x1b' => KeyCode::Esc,
+        ' ' => KeyCode::Space,
+        '\0' => {
+            modifiers.ctrl = true;
+            KeyCode::Space
+        }
+        '\x01'..='\x1a' => {
+            modifiers.ctrl = true;
+            KeyCode::Char((c as u8 + b'a' - 1) as char)
+        }
+        '\x1c'..='\x1f' => {
+            modifiers.ctrl = true;
+            KeyCode::Char((c as u8 + b'@') as char)
+        }
+        'A'..='Z' => {
+            modifiers.shift = true;
+            KeyCode::Char(c.to_ascii_lowercase())
+        }
+        c if c.is_control() => return None,
+        c => KeyCode::Char(c),
+    };
+    Some(key(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escape_sequences::BRACKETED_PASTE_END;
+    use crate::key_parser;
+
+    fn parsed(raw: &str) -> Option<KeyCombination> {
+        Some(key_parser::parse(raw).unwrap())
+    }
+
+    fn tokens(decoder: &mut InputDecoder, data: &[u8]) -> Vec<Vec<u8>> {
+        decoder.decode(data).into_iter().map(|k| k.bytes).collect()
+    }
+
+    #[test]
+    fn test_splits_keys() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(
+            tokens(
+                &mut decoder,
+                "ab\x1b[A\x1bOP\x1b[99;5u\u{e9}\x03\x1bx".as_bytes()
+            ),
+            vec![
+                b"a".to_vec(),
+                b"b".to_vec(),
+                b"\x1b[A".to_vec(),
+                b"\x1bOP".to_vec(),
+                b"\x1b[99;5u".to_vec(),
+                "\u{e9}".as_bytes().to_vec(),
+                vec![0x03],
+                b"\x1bx".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sequence_split_across_reads() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.decode(b"\x1b[54").is_empty());
+        assert!(decoder.time_until_flush().is_some());
+        let keys = decoder.decode(b";5u");
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key, parsed("[ctrl][6]"));
+        assert_eq!(decoder.time_until_flush(), None);
+
+        // A character split across reads
+        assert!(decoder.decode(b"\xc3").is_empty());
+        let keys = decoder.decode(b"\xa9\x1bO");
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key, parsed("[\u{e9}]"));
+        let keys = decoder.decode(b"A");
+        assert_eq!(keys[0].bytes, b"\x1bOA");
+        assert_eq!(keys[0].key, parsed("[up]"));
+    }
+
+    #[test]
+    fn test_lone_escape_flushes_as_escape_key() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.decode(b"\x1b").is_empty());
+        let keys = decoder.flush();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].bytes, b"\x1b");
+        assert_eq!(keys[0].key, parsed("[esc]"));
+
+        assert!(decoder.decode(b"\x1b[1;").is_empty());
+        let keys = decoder.flush();
+        assert_eq!(keys[0].bytes, b"\x1b[1;");
+        assert_eq!(keys[0].key, None);
+
+        // Alt+[ looks like the start of a CSI sequence until it times out
+        assert!(decoder.decode(b"\x1b[").is_empty());
+        let keys = decoder.flush();
+        assert_eq!(keys[0].bytes, b"\x1b[");
+        assert_eq!(keys[0].key, parsed("[alt][[]"));
+
+        // Or until something follows that can't be part of one
+        let keys = decoder.decode(b"\x1b[\r");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key, parsed("[alt][[]"));
+        assert_eq!(keys[1].key, parsed("[enter]"));
+
+        // Alt+O looks like the start of an SS3 sequence
+        assert!(decoder.decode(b"\x1bO").is_empty());
+        assert_eq!(decoder.flush()[0].key, parsed("[alt][shift][o]"));
+    }
+
+    #[test]
+    fn test_invalid_utf8_flushed() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.decode(b"\xff").is_empty());
+        let keys = decoder.flush();
+        assert_eq!(keys[0].bytes, b"\xff");
+        assert_eq!(keys[0].key, None);
+        assert_eq!(decoder.decode(b"a")[0].key, parsed("[a]"));
+    }
+
+    #[test]
+    fn test_same_key_in_every_protocol() {
+        // Legacy, CSI u, and Kitty with CapsLock and an explicit press event
+        for bytes in [&b"\x03"[..], b"\x1b[99;5u", b"\x1b[99;69u", b"\x1b[99;5:1u"] {
+            assert_eq!(decode_key(bytes), parsed("[ctrl][c]"), "{:?}", bytes);
+        }
+        for bytes in [&b"\x1b[24~"[..], b"\x1b[24;1~", b"\x1b[24;129~"] {
+            assert_eq!(decode_key(bytes), parsed("[f12]"), "{:?}", bytes);
+        }
+        assert_eq!(decode_key(b"\x1b[1;5A"), parsed("[ctrl][up]"));
+        assert_eq!(decode_key(b"\x1b[3;5~"), parsed("[ctrl][delete]"));
+        assert_eq!(decode_key(b"\x1b[97;6u"), parsed("[ctrl][shift][a]"));
+        assert_eq!(decode_key(b"A"), parsed("[shift][a]"));
+        assert_eq!(decode_key(b"\x1bl"), parsed("[alt][l]"));
+        assert_eq!(decode_key(b"\x1b[99;9u"), parsed("[super][c]"));
+        assert_eq!(decode_key(b"\x1b[57376;2u"), parsed("[shift][f13]"));
+        assert_eq!(decode_key(b"\x1b[233;5u"), parsed("[ctrl][\u{e9}]"));
+        // Kitty alternate keys don't change the base key
+        assert_eq!(decode_key(b"\x1b[97:65;6u"), parsed("[ctrl][shift][a]"));
+    }
+
+    #[test]
+    fn test_control_characters_keep_their_keys() {
+        assert_eq!(decode_key(b"\x08"), parsed("[ctrl][h]"));
+        assert_eq!(decode_key(b"\x7f"), parsed("[backspace]"));
+        assert_eq!(decode_key(b"\n"), parsed("[ctrl][j]"));
+        assert_eq!(decode_key(b"\r"), parsed("[enter]"));
+        assert_eq!(decode_key(b"\x1e"), parsed("[ctrl][^]"));
+        assert_eq!(decode_key(b"\x00"), parsed("[ctrl][space]"));
+    }
+
+    #[test]
+    fn test_non_key_sequences() {
+        // Key release and repeat
+        assert_eq!(decode_key(b"\x1b[99;5:3u"), None);
+        assert_eq!(decode_key(b"\x1b[99;5:2u"), None);
+        // Kitty functional keys with no name, such as the left shift key
+        assert_eq!(decode_key(b"\x1b[57441u"), None);
+        // SGR mouse report and focus in
+        assert_eq!(decode_key(b"\x1b[<0;10;5M"), None);
+        assert_eq!(decode_key(b"\x1b[I"), None);
+    }
+
+    #[test]
+    fn test_x10_mouse_report_is_one_input() {
+        let mut decoder = InputDecoder::new();
+        // The raw bytes after CSI M look like keys, and may not all be read
+        // at once
+        let keys = decoder.decode(b"a\x1b[M #");
+        assert_eq!(keys.len(), 1);
+        assert!(decoder.time_until_flush().is_some());
+        let keys = decoder.decode(b"\x1eb");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].bytes, b"\x1b[M #\x1e");
+        assert_eq!(keys[0].key, None);
+        assert_eq!(keys[1].key, parsed("[b]"));
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        let mut decoder = InputDecoder::new();
+        let keys = decoder.decode(b"a\x1b[200~\x1e\x1b[99;5u\x1b[20");
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key, parsed("[a]"));
+        // A paste doesn't time out
+        assert_eq!(decoder.time_until_flush(), None);
+        assert!(decoder.flush().is_empty());
+
+        // The end marker split across reads
+        let keys = decoder.decode(b"1~\x03");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].bytes, b"\x1b[200~\x1e\x1b[99;5u\x1b[201~");
+        assert!(keys[0].paste);
+        assert_eq!(keys[0].key, None);
+        assert_eq!(keys[1].key, parsed("[ctrl][c]"));
+        assert!(!keys[1].paste);
+
+        // An Escape key press right before a paste
+        let keys = decoder.decode(b"\x1b\x1b[200~x\x1b[201~");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].bytes, b"\x1b");
+        assert_eq!(keys[0].key, parsed("[esc]"));
+        assert_eq!(keys[1].bytes, b"\x1b[200~x\x1b[201~");
+        assert!(keys[1].paste);
+    }
+
+    #[test]
+    fn test_take_pending_ends_paste() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.decode(b"\x1b[200~abc").is_empty());
+        assert_eq!(decoder.take_pending(), b"\x1b[200~abc");
+        assert_eq!(decoder.decode(b"q")[0].key, parsed("[q]"));
+    }
+
+    #[test]
+    fn test_partial_marker_len() {
+        assert_eq!(partial_marker_len(b"abc\x1b[20", BRACKETED_PASTE_END), 4);
+        assert_eq!(partial_marker_len(b"abc\x1b", BRACKETED_PASTE_END), 1);
+        assert_eq!(partial_marker_len(b"abc", BRACKETED_PASTE_END), 0);
+        assert_eq!(partial_marker_len(b"", BRACKETED_PASTE_END), 0);
+    }
+}
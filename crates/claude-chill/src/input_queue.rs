@@ -0,0 +1,133 @@
+//! Input on its way to the child's pty.
+//!
+//! The pty master is nonblocking, and a large paste can fill the kernel's
+//! buffer before the child reads it. Whatever doesn't fit waits here and is
+//! written when poll reports the master writable, so the proxy keeps
+//! rendering output in the meantime.
+
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::unistd::write;
+use std::os::fd::AsFd;
+
+/// Once this much input is waiting, stdin isn't read until some of it has
+/// been written.
+pub const INPUT_QUEUE_HIGH_WATER: usize = 1024 * 1024;
+
+#[derive(Debug, Default)]
+pub struct InputQueue {
+    data: Vec<u8>,
+    /// How much of `data` has been written already
+    written: usize,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `data` behind anything still waiting and write as much as the
+    /// pty takes now.
+    pub fn send<F: AsFd>(&mut self, fd: &F, data: &[u8]) -> Result<()> {
+        self.data.extend_from_slice(data);
+        self.write_to(fd)
+    }
+
+    /// Write waiting input until the pty would block.
+    pub fn write_to<F: AsFd>(&mut self, fd: &F) -> Result<()> {
+        while self.written < self.data.len() {
+            match write(fd, &self.data[self.written..]) {
+                Ok(n) => self.written += n,
+                Err(Errno::EINTR) => continue,
+                Err(Errno::EAGAIN) => break,
+                Err(e) => anyhow::bail!("write to pty failed: {}", e),
+            }
+        }
+        if self.written == self.data.len() {
+            self.data.clear();
+            self.written = 0;
+        } else if self.written >= self.data.len() / 2 {
+            self.data.drain(..self.written);
+            self.written = 0;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() - self.written
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether stdin should wait for the child to catch up.
+    pub fn is_full(&self) -> bool {
+        self.len() >= INPUT_QUEUE_HIGH_WATER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::fcntl::{FcntlArg, OFlag, fcntl};
+    use nix::unistd::{pipe, read};
+    use std::os::fd::OwnedFd;
+
+    fn nonblocking_pipe() -> (OwnedFd, OwnedFd) {
+        let (reader, writer) = pipe().unwrap();
+        for fd in [&reader, &writer] {
+            fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+        }
+        (reader, writer)
+    }
+
+    fn read_all(fd: &OwnedFd) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 65536];
+        while let Ok(n @ 1..) = read(fd, &mut buf) {
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    }
+
+    #[test]
+    fn test_small_write_goes_straight_through() {
+        let (reader, writer) = nonblocking_pipe();
+        let mut queue = InputQueue::new();
+        queue.send(&writer, b"hello").unwrap();
+        assert!(queue.is_empty());
+        assert_eq!(read_all(&reader), b"hello");
+    }
+
+    #[test]
+    fn test_full_pty_keeps_the_rest_in_order() {
+        let (reader, writer) = nonblocking_pipe();
+        let mut queue = InputQueue::new();
+        let paste: Vec<u8> = (0..INPUT_QUEUE_HIGH_WATER * 2).map(|i| i as u8).collect();
+        queue.send(&writer, &paste).unwrap();
+        assert!(!queue.is_empty());
+        assert!(queue.len() < paste.len());
+        queue.send(&writer, b"typed").unwrap();
+
+        let mut received = Vec::new();
+        while !queue.is_empty() {
+            received.extend(read_all(&reader));
+            queue.write_to(&writer).unwrap();
+        }
+        received.extend(read_all(&reader));
+        assert_eq!(&received[..paste.len()], &paste[..]);
+        assert_eq!(&received[paste.len()..], b"typed");
+    }
+
+    #[test]
+    fn test_high_water() {
+        let (_reader, writer) = nonblocking_pipe();
+        let mut queue = InputQueue::new();
+        assert!(!queue.is_full());
+        queue
+            .send(&writer, &vec![b'x'; INPUT_QUEUE_HIGH_WATER * 2])
+            .unwrap();
+        assert!(queue.is_full());
+    }
+}
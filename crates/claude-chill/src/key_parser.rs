@@ -1,5 +1,11 @@
 use std::fmt;
 
+/// Function keys go up to F24.
+const MAX_FUNCTION_KEY: u8 = 24;
+
+/// Kitty protocol key code of F13; F14 and up follow it.
+pub const KITTY_F13: u32 = 57376;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParseKeyError {
     pub raw: String,
@@ -28,6 +34,40 @@ pub struct Modifiers {
     pub ctrl: bool,
     pub shift: bool,
     pub alt: bool,
+    /// Super (Cmd, Windows), hyper and meta only exist in the Kitty protocol
+    pub super_key: bool,
+    pub hyper: bool,
+    pub meta: bool,
+}
+
+impl Modifiers {
+    /// Modifier bits as used in the Kitty protocol, which extends xterm's.
+    pub fn kitty_bits(&self) -> u32 {
+        u32::from(self.shift)
+            | u32::from(self.alt) << 1
+            | u32::from(self.ctrl) << 2
+            | u32::from(self.super_key) << 3
+            | u32::from(self.hyper) << 4
+            | u32::from(self.meta) << 5
+    }
+
+    /// Modifiers from Kitty protocol bits. Bits past meta, for CapsLock and
+    /// NumLock, are ignored.
+    pub fn from_kitty_bits(bits: u32) -> Self {
+        Self {
+            shift: bits & 1 != 0,
+            alt: bits & 2 != 0,
+            ctrl: bits & 4 != 0,
+            super_key: bits & 8 != 0,
+            hyper: bits & 16 != 0,
+            meta: bits & 32 != 0,
+        }
+    }
+
+    /// Whether any modifier legacy encodings can't carry is held.
+    pub fn has_kitty_only(&self) -> bool {
+        self.super_key || self.hyper || self.meta
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -58,32 +98,11 @@ pub struct KeyCombination {
 }
 
 impl KeyCombination {
+    /// The key as a legacy terminal sends it. Super, hyper and meta can't be
+    /// encoded and are left out.
     pub fn to_escape_sequence(&self) -> Vec<u8> {
         key_to_escape_sequence(&self.code, &self.modifiers)
     }
-
-    pub fn to_kitty_sequence(&self) -> Option<Vec<u8>> {
-        let codepoint = match &self.code {
-            KeyCode::Char(c) => *c as u32,
-            KeyCode::Esc => 27,
-            KeyCode::Enter => 13,
-            KeyCode::Tab => 9,
-            KeyCode::Backspace => 127,
-            KeyCode::Space => 32,
-            _ => return None,
-        };
-
-        let modifier = 1
-            + if self.modifiers.shift { 1 } else { 0 }
-            + if self.modifiers.alt { 2 } else { 0 }
-            + if self.modifiers.ctrl { 4 } else { 0 };
-
-        if modifier == 1 {
-            Some(format!("\x1b[{}u", codepoint).into_bytes())
-        } else {
-            Some(format!("\x1b[{};{}u", codepoint, modifier).into_bytes())
-        }
-    }
 }
 
 impl fmt::Display for KeyCombination {
@@ -97,6 +116,15 @@ impl fmt::Display for KeyCombination {
         if self.modifiers.alt {
             write!(f, "[alt]")?;
         }
+        if self.modifiers.super_key {
+            write!(f, "[super]")?;
+        }
+        if self.modifiers.hyper {
+            write!(f, "[hyper]")?;
+        }
+        if self.modifiers.meta {
+            write!(f, "[meta]")?;
+        }
         let key_name = match &self.code {
             KeyCode::Char(c) => format!("[{}]", c),
             KeyCode::F(n) => format!("[f{}]", n),
@@ -138,6 +166,10 @@ pub fn parse(raw: &str) -> Result<KeyCombination, ParseKeyError> {
 
         let start = i + 1;
         let mut end = start;
+        // `[]]` is the ] key
+        if chars.get(start) == Some(&']') && chars.get(start + 1) == Some(&']') {
+            end += 1;
+        }
         while end < chars.len() && chars[end] != ']' {
             end += 1;
         }
@@ -153,6 +185,9 @@ pub fn parse(raw: &str) -> Result<KeyCombination, ParseKeyError> {
             "ctrl" | "control" => modifiers.ctrl = true,
             "shift" => modifiers.shift = true,
             "alt" => modifiers.alt = true,
+            "super" | "cmd" | "win" => modifiers.super_key = true,
+            "hyper" => modifiers.hyper = true,
+            "meta" => modifiers.meta = true,
             _ => {
                 if key_code.is_some() {
                     return Err(ParseKeyError::new(raw, "multiple key codes specified"));
@@ -168,6 +203,18 @@ pub fn parse(raw: &str) -> Result<KeyCombination, ParseKeyError> {
     }
 }
 
+/// Parse keys separated by spaces, e.g. the chord `[ctrl][a] [l]`.
+pub fn parse_sequence(raw: &str) -> Result<Vec<KeyCombination>, ParseKeyError> {
+    let keys = raw
+        .split_whitespace()
+        .map(parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err(ParseKeyError::new(raw, "no key code specified"));
+    }
+    Ok(keys)
+}
+
 fn parse_key_code(token: &str, raw: &str) -> Result<KeyCode, ParseKeyError> {
     let code = match token {
         "[" => KeyCode::Char('['),
@@ -187,19 +234,12 @@ fn parse_key_code(token: &str, raw: &str) -> Result<KeyCode, ParseKeyError> {
         "left" => KeyCode::Left,
         "right" => KeyCode::Right,
         "space" => KeyCode::Space,
-        "f1" => KeyCode::F(1),
-        "f2" => KeyCode::F(2),
-        "f3" => KeyCode::F(3),
-        "f4" => KeyCode::F(4),
-        "f5" => KeyCode::F(5),
-        "f6" => KeyCode::F(6),
-        "f7" => KeyCode::F(7),
-        "f8" => KeyCode::F(8),
-        "f9" => KeyCode::F(9),
-        "f10" => KeyCode::F(10),
-        "f11" => KeyCode::F(11),
-        "f12" => KeyCode::F(12),
-        s if s.len() == 1 => KeyCode::Char(s.chars().next().unwrap_or(' ')),
+        s if s.chars().count() == 1 => KeyCode::Char(s.chars().next().unwrap_or(' ')),
+        s if let Some(n) = s.strip_prefix('f').and_then(|n| n.parse::<u8>().ok())
+            && (1..=MAX_FUNCTION_KEY).contains(&n) =>
+        {
+            KeyCode::F(n)
+        }
         _ => return Err(ParseKeyError::new(raw, format!("unknown key: {}", token))),
     };
     Ok(code)
@@ -234,6 +274,8 @@ fn key_to_escape_sequence(code: &KeyCode, modifiers: &Modifiers) -> Vec<u8> {
         KeyCode::Left => arrow_key(b'D', modifier_code),
         KeyCode::Insert => modified_key(b"2", modifier_code),
         KeyCode::Delete => modified_key(b"3", modifier_code),
+        // F13 and up are sent as shifted F1 to F12 by xterm
+        KeyCode::F(n @ 13..) => function_key(n - 12, modifier_code.max(1) + 1),
         KeyCode::F(n) => function_key(*n, modifier_code),
         KeyCode::Enter => {
             if modifiers.alt {
@@ -324,8 +366,8 @@ fn arrow_key(direction: u8, modifier: u8) -> Vec<u8> {
     }
 }
 
-fn function_key(n: u8, modifier: u8) -> Vec<u8> {
-    let code = match n {
+fn function_key_code(n: u8) -> Option<u32> {
+    Some(match n {
         1 => 11,
         2 => 12,
         3 => 13,
@@ -338,7 +380,13 @@ fn function_key(n: u8, modifier: u8) -> Vec<u8> {
         10 => 21,
         11 => 23,
         12 => 24,
-        _ => return b"\x1b[24~".to_vec(),
+        _ => return None,
+    })
+}
+
+fn function_key(n: u8, modifier: u8) -> Vec<u8> {
+    let Some(code) = function_key_code(n) else {
+        return b"\x1b[24~".to_vec();
     };
 
     if modifier == 0 {
@@ -352,6 +400,40 @@ fn function_key(n: u8, modifier: u8) -> Vec<u8> {
 mod tests {
     use super::*;
 
+    /// The key as the Kitty keyboard protocol encodes it.
+    fn kitty_sequence(key: &KeyCombination) -> Vec<u8> {
+        let (number, final_byte) = match &key.code {
+            KeyCode::Char(c) => (*c as u32, 'u'),
+            KeyCode::Esc => (27, 'u'),
+            KeyCode::Enter => (13, 'u'),
+            KeyCode::Tab => (9, 'u'),
+            KeyCode::Backspace => (127, 'u'),
+            KeyCode::Space => (32, 'u'),
+            KeyCode::Insert => (2, '~'),
+            KeyCode::Delete => (3, '~'),
+            KeyCode::PageUp => (5, '~'),
+            KeyCode::PageDown => (6, '~'),
+            KeyCode::Up => (1, 'A'),
+            KeyCode::Down => (1, 'B'),
+            KeyCode::Right => (1, 'C'),
+            KeyCode::Left => (1, 'D'),
+            KeyCode::Home => (1, 'H'),
+            KeyCode::End => (1, 'F'),
+            KeyCode::F(1) => (1, 'P'),
+            KeyCode::F(2) => (1, 'Q'),
+            KeyCode::F(4) => (1, 'S'),
+            KeyCode::F(n @ 13..) => (KITTY_F13 + u32::from(*n) - 13, 'u'),
+            KeyCode::F(n) => (function_key_code(*n).unwrap_or(24), '~'),
+        };
+
+        let modifier = 1 + key.modifiers.kitty_bits();
+        match (modifier, final_byte) {
+            (1, 'u' | '~') => format!("\x1b[{}{}", number, final_byte).into_bytes(),
+            (1, _) => format!("\x1b[{}", final_byte).into_bytes(),
+            _ => format!("\x1b[{};{}{}", number, modifier, final_byte).into_bytes(),
+        }
+    }
+
     #[test]
     fn test_parse_simple_key() {
         let key = parse("[f12]").unwrap();
@@ -450,4 +532,127 @@ mod tests {
         let key = parse("[ctrl][\\]").unwrap();
         assert_eq!(key.to_escape_sequence(), vec![0x1C]);
     }
+
+    #[test]
+    fn test_parse_sequence() {
+        let keys = parse_sequence("[ctrl][a]  [l]").unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].to_escape_sequence(), vec![0x01]);
+        assert_eq!(keys[1].to_escape_sequence(), b"l");
+        assert_eq!(parse_sequence("[f12]").unwrap().len(), 1);
+        assert!(parse_sequence("  ").is_err());
+        assert!(parse_sequence("[ctrl][a] l").is_err());
+    }
+
+    #[test]
+    fn test_parse_extended_keys() {
+        let key = parse("[super][hyper][meta][f24]").unwrap();
+        assert_eq!(key.code, KeyCode::F(24));
+        assert!(key.modifiers.super_key && key.modifiers.hyper && key.modifiers.meta);
+        assert_eq!(parse("[cmd][k]").unwrap().to_string(), "[super][k]");
+        assert_eq!(parse("[]]").unwrap().code, KeyCode::Char(']'));
+        assert_eq!(parse("[ctrl][]]").unwrap().to_escape_sequence(), vec![0x1D]);
+        assert_eq!(parse("[7]").unwrap().code, KeyCode::Char('7'));
+        assert!(parse("[f25]").is_err());
+        assert!(parse("[f0]").is_err());
+    }
+
+    #[test]
+    fn test_kitty_sequences() {
+        let kitty = |raw: &str| String::from_utf8(kitty_sequence(&parse(raw).unwrap())).unwrap();
+        assert_eq!(kitty("[ctrl][6]"), "\x1b[54;5u");
+        assert_eq!(kitty("[esc]"), "\x1b[27u");
+        assert_eq!(kitty("[up]"), "\x1b[A");
+        assert_eq!(kitty("[ctrl][up]"), "\x1b[1;5A");
+        assert_eq!(kitty("[f1]"), "\x1b[P");
+        assert_eq!(kitty("[f3]"), "\x1b[13~");
+        assert_eq!(kitty("[shift][f3]"), "\x1b[13;2~");
+        assert_eq!(kitty("[f12]"), "\x1b[24~");
+        assert_eq!(kitty("[f13]"), "\x1b[57376u");
+        assert_eq!(kitty("[pagedown]"), "\x1b[6~");
+        assert_eq!(kitty("[super][delete]"), "\x1b[3;9~");
+        assert_eq!(kitty("[ctrl][meta][a]"), "\x1b[97;37u");
+    }
+
+    #[test]
+    fn test_legacy_f13_is_shifted_f1() {
+        assert_eq!(parse("[f13]").unwrap().to_escape_sequence(), b"\x1b[11;2~");
+        assert_eq!(
+            parse("[ctrl][f24]").unwrap().to_escape_sequence(),
+            b"\x1b[24;6~"
+        );
+    }
+
+    const ROUND_TRIP_KEYS: &[&str] = &[
+        "[a]",
+        "[ctrl][c]",
+        "[shift][a]",
+        "[alt][l]",
+        "[ctrl][alt][x]",
+        "[1]",
+        "[;]",
+        "[/]",
+        "[]]",
+        "[[]",
+        "[\\]",
+        "[enter]",
+        "[alt][enter]",
+        "[esc]",
+        "[tab]",
+        "[shift][tab]",
+        "[backspace]",
+        "[space]",
+        "[insert]",
+        "[delete]",
+        "[home]",
+        "[ctrl][end]",
+        "[pageup]",
+        "[ctrl][shift][pagedown]",
+        "[up]",
+        "[ctrl][down]",
+        "[alt][left]",
+        "[shift][right]",
+        "[f1]",
+        "[ctrl][f4]",
+        "[f5]",
+        "[f12]",
+    ];
+
+    /// Keys whose legacy encoding is shared with another key
+    const KITTY_ONLY_KEYS: &[&str] = &[
+        "[ctrl][6]",
+        "[ctrl][shift][a]",
+        "[ctrl][backspace]",
+        "[f13]",
+        "[shift][f24]",
+        "[super][k]",
+        "[hyper][meta][f5]",
+    ];
+
+    #[test]
+    fn test_round_trip_display() {
+        for raw in ROUND_TRIP_KEYS.iter().chain(KITTY_ONLY_KEYS) {
+            let key = parse(raw).unwrap();
+            assert_eq!(key.to_string(), *raw);
+            assert_eq!(parse(&key.to_string()).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_kitty_encoding() {
+        for raw in ROUND_TRIP_KEYS.iter().chain(KITTY_ONLY_KEYS) {
+            let key = parse(raw).unwrap();
+            let decoded = crate::input_decoder::decode_key(&kitty_sequence(&key));
+            assert_eq!(decoded, Some(key), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_legacy_encoding() {
+        for raw in ROUND_TRIP_KEYS {
+            let key = parse(raw).unwrap();
+            let decoded = crate::input_decoder::decode_key(&key.to_escape_sequence());
+            assert_eq!(decoded, Some(key), "{}", raw);
+        }
+    }
 }
@@ -0,0 +1,178 @@
+//! The child's Kitty keyboard protocol stack on the main screen.
+//!
+//! Main-screen output is rendered from the VT emulator, which drops
+//! `CSI > flags u` (push), `CSI < n u` (pop), `CSI = flags ; mode u` (set)
+//! and `CSI ? u` (query). They are picked out of the output here so the
+//! proxy can forward them, and the stack they build is kept so it can be
+//! taken down for lookback and put back afterwards, or popped on exit.
+//!
+//! The alternate screen has a stack of its own in the terminal, and output
+//! there is written as is, so only main-screen output is scanned.
+
+/// Longest keyboard sequence held back while waiting for the rest of it.
+const MAX_PARTIAL_LEN: usize = 32;
+
+/// Kitty keyboard protocol flags for each entry the child pushed.
+#[derive(Debug, Default)]
+pub struct KeyboardModes {
+    stack: Vec<u32>,
+    /// Start of a sequence cut off at the end of the last output
+    partial: Vec<u8>,
+}
+
+impl KeyboardModes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find the keyboard protocol sequences in `data`, apply them to the
+    /// stack, and return them in order.
+    pub fn scan(&mut self, data: &[u8]) -> Vec<u8> {
+        let joined;
+        let data = if self.partial.is_empty() {
+            data
+        } else {
+            let mut buf = std::mem::take(&mut self.partial);
+            buf.extend_from_slice(data);
+            joined = buf;
+            &joined
+        };
+
+        let mut found = Vec::new();
+        let mut pos = 0;
+        while let Some(start) = memchr::memchr(0x1b, &data[pos..]).map(|i| pos + i) {
+            match sequence_len(&data[start..]) {
+                Some(Some(len)) => {
+                    let sequence = &data[start..start + len];
+                    self.apply(sequence);
+                    found.extend_from_slice(sequence);
+                    pos = start + len;
+                }
+                Some(None) => pos = start + 1,
+                None => {
+                    if data.len() - start <= MAX_PARTIAL_LEN {
+                        self.partial = data[start..].to_vec();
+                    }
+                    break;
+                }
+            }
+        }
+        found
+    }
+
+    fn apply(&mut self, sequence: &[u8]) {
+        let body = &sequence[3..sequence.len() - 1];
+        let mut params = std::str::from_utf8(body)
+            .unwrap_or_default()
+            .split(';')
+            .map(|p| p.split(':').next().unwrap_or_default().parse::<u32>().ok());
+        let first = params.next().flatten();
+        match sequence[2] {
+            b'>' => self.stack.push(first.unwrap_or(0)),
+            b'<' => {
+                let n = first.unwrap_or(1).max(1) as usize;
+                self.stack.truncate(self.stack.len().saturating_sub(n));
+            }
+            b'=' => {
+                // Setting flags with nothing pushed changes the terminal's
+                // own flags, which aren't restored
+                let Some(top) = self.stack.last_mut() else {
+                    return;
+                };
+                let flags = first.unwrap_or(0);
+                match params.next().flatten().unwrap_or(1) {
+                    2 => *top |= flags,
+                    3 => *top &= !flags,
+                    _ => *top = flags,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Number of entries the child has pushed and not popped.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Sequence that pops everything the child pushed.
+    pub fn pop_all(&self) -> Vec<u8> {
+        if self.stack.is_empty() {
+            return Vec::new();
+        }
+        format!("\x1b[<{}u", self.stack.len()).into_bytes()
+    }
+
+    /// Sequences that push the child's stack again after `pop_all`.
+    pub fn push_all(&self) -> Vec<u8> {
+        self.stack
+            .iter()
+            .flat_map(|flags| format!("\x1b[>{}u", flags).into_bytes())
+            .collect()
+    }
+}
+
+/// Length of the keyboard protocol sequence at the start of `data`:
+/// Some(None) if there isn't one, None if `data` ends before it is known.
+fn sequence_len(data: &[u8]) -> Option<Option<usize>> {
+    for (i, &b) in data.iter().enumerate() {
+        let valid = match i {
+            0 => b == 0x1b,
+            1 => b == b'[',
+            2 => matches!(b, b'>' | b'<' | b'=' | b'?'),
+            _ if b == b'u' => return Some(Some(i + 1)),
+            _ => b.is_ascii_digit() || b == b';' || b == b':',
+        };
+        if !valid {
+            return Some(None);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_finds_keyboard_sequences() {
+        let mut modes = KeyboardModes::new();
+        let found = modes.scan(b"hi\x1b[>1u\x1b[31mred\x1b[?u\x1b[A");
+        assert_eq!(found, b"\x1b[>1u\x1b[?u");
+        assert_eq!(modes.depth(), 1);
+        assert_eq!(modes.scan(b"\x1b[>5u plain"), b"\x1b[>5u");
+        assert_eq!(modes.depth(), 2);
+        assert_eq!(modes.scan(b"\x1b[<u"), b"\x1b[<u");
+        assert_eq!(modes.depth(), 1);
+    }
+
+    #[test]
+    fn test_sequence_split_across_output() {
+        let mut modes = KeyboardModes::new();
+        assert!(modes.scan(b"text\x1b[>").is_empty());
+        assert_eq!(modes.scan(b"3u more"), b"\x1b[>3u");
+        assert_eq!(modes.push_all(), b"\x1b[>3u");
+    }
+
+    #[test]
+    fn test_pop_and_push_all() {
+        let mut modes = KeyboardModes::new();
+        assert!(modes.pop_all().is_empty());
+        modes.scan(b"\x1b[>1u\x1b[>u\x1b[=8;2u");
+        assert_eq!(modes.pop_all(), b"\x1b[<2u");
+        assert_eq!(modes.push_all(), b"\x1b[>1u\x1b[>8u");
+        modes.scan(b"\x1b[=1;3u\x1b[<5u");
+        assert_eq!(modes.depth(), 0);
+    }
+
+    #[test]
+    fn test_set_flags() {
+        let mut modes = KeyboardModes::new();
+        modes.scan(b"\x1b[=3u");
+        assert_eq!(modes.depth(), 0);
+        modes.scan(b"\x1b[>1u\x1b[=3;2u\x1b[=1;3u");
+        assert_eq!(modes.push_all(), b"\x1b[>2u");
+        modes.scan(b"\x1b[=9u");
+        assert_eq!(modes.push_all(), b"\x1b[>9u");
+    }
+}
@@ -0,0 +1,359 @@
+//! Hotkey bindings: which keys pressed on stdin trigger proxy actions.
+//!
+//! Keys are matched as decoded key presses, so a binding works whatever
+//! keyboard protocol the terminal is in. Legacy encodings are ambiguous
+//! (`[ctrl][6]` and `[ctrl][^]` both send 0x1E), so a legacy-encoded press
+//! also matches a bound key whose legacy sequence decodes to it.
+//!
+//! A binding can also be a chord of a prefix key followed by one more key,
+//! tmux style. Matching only covers the prefix; the proxy reads the key
+//! after it and looks the chord up with [`Keymap::match_chord`].
+//!
+//! Guarded keys aren't bound to an action; they go to the child only when
+//! pressed twice in a row, so a stray press can't do damage.
+
+use crate::input_decoder::{self, KeyInput};
+use crate::key_parser::{self, KeyCombination, ParseKeyError};
+use serde::Deserialize;
+use std::fmt;
+
+/// Something the proxy can do when a bound key is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAction {
+    /// Enter lookback mode, or leave it
+    ToggleLookback,
+    /// Show history over the live screen, or hide it
+    TogglePeek,
+    /// Leave lookback or peek mode. Outside them the key goes to the child
+    ExitLookback,
+    /// Write history to the terminal so it lands in the scrollback
+    DumpHistory,
+    /// Export history to `export_path` or a timestamped file
+    ExportHistory,
+    /// Repaint the whole screen
+    ForceRedraw,
+    /// Write output to the terminal as is instead of rendering it
+    TogglePassthrough,
+    /// Forget all history recorded so far
+    ClearHistory,
+}
+
+impl KeyAction {
+    pub const ALL: [KeyAction; 8] = [
+        KeyAction::ToggleLookback,
+        KeyAction::TogglePeek,
+        KeyAction::ExitLookback,
+        KeyAction::DumpHistory,
+        KeyAction::ExportHistory,
+        KeyAction::ForceRedraw,
+        KeyAction::TogglePassthrough,
+        KeyAction::ClearHistory,
+    ];
+
+    /// The name used in the `[keys]` config table.
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyAction::ToggleLookback => "toggle_lookback",
+            KeyAction::TogglePeek => "toggle_peek",
+            KeyAction::ExitLookback => "exit_lookback",
+            KeyAction::DumpHistory => "dump_history",
+            KeyAction::ExportHistory => "export_history",
+            KeyAction::ForceRedraw => "force_redraw",
+            KeyAction::TogglePassthrough => "toggle_passthrough",
+            KeyAction::ClearHistory => "clear_history",
+        }
+    }
+}
+
+impl fmt::Display for KeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMatch {
+    Complete(KeyAction),
+    /// The key is the prefix key of a chord
+    Prefix,
+    None,
+}
+
+/// The key presses that count as one configured key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BoundKey {
+    key: KeyCombination,
+    /// What the key's legacy sequence decodes to, if that's another key
+    legacy: Option<KeyCombination>,
+}
+
+impl BoundKey {
+    fn new(key: &KeyCombination) -> Self {
+        // Legacy sequences drop super, hyper and meta, so they'd match the
+        // key without them
+        let legacy = (!key.modifiers.has_kitty_only())
+            .then(|| input_decoder::decode_key(&key.to_escape_sequence()))
+            .flatten()
+            .filter(|legacy| legacy != key);
+        Self {
+            key: key.clone(),
+            legacy,
+        }
+    }
+
+    fn matches(&self, input: &KeyInput) -> bool {
+        input.key.as_ref().is_some_and(|pressed| {
+            *pressed == self.key || (input.is_legacy() && self.legacy.as_ref() == Some(pressed))
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Binding {
+    key: String,
+    action: KeyAction,
+    prefix: Option<BoundKey>,
+    last: BoundKey,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    guarded: Vec<BoundKey>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind `key` (in `key_parser` syntax, or a prefix and a key separated
+    /// by a space) to `action`. A key that is already bound is rebound.
+    pub fn bind(&mut self, key: &str, action: KeyAction) -> Result<(), ParseKeyError> {
+        let (prefix, last) = match key_parser::parse_sequence(key)?.as_slice() {
+            [last] => (None, BoundKey::new(last)),
+            [prefix, last] => (Some(BoundKey::new(prefix)), BoundKey::new(last)),
+            _ => {
+                return Err(ParseKeyError::new(
+                    key,
+                    "a chord is a prefix key followed by one key",
+                ));
+            }
+        };
+        self.bindings
+            .retain(|b| b.prefix != prefix || b.last != last);
+        self.bindings.push(Binding {
+            key: key.to_string(),
+            action,
+            prefix,
+            last,
+        });
+        Ok(())
+    }
+
+    /// Make `key` need a second press before it is sent to the child.
+    pub fn guard(&mut self, key: &str) -> Result<(), ParseKeyError> {
+        self.guarded.push(BoundKey::new(&key_parser::parse(key)?));
+        Ok(())
+    }
+
+    pub fn is_guarded(&self, key: &KeyInput) -> bool {
+        self.guarded.iter().any(|g| g.matches(key))
+    }
+
+    pub fn is_bound(&self, action: KeyAction) -> bool {
+        self.bindings.iter().any(|b| b.action == action)
+    }
+
+    /// The first key bound to `action`, as written in the config.
+    pub fn key_for(&self, action: KeyAction) -> Option<&str> {
+        self.bindings
+            .iter()
+            .find(|b| b.action == action)
+            .map(|b| b.key.as_str())
+    }
+
+    /// Match a key press against every bound key and chord prefix.
+    pub fn match_key(&self, key: &KeyInput) -> KeyMatch {
+        let mut prefix = false;
+        for binding in &self.bindings {
+            match &binding.prefix {
+                Some(p) => prefix |= p.matches(key),
+                None if binding.last.matches(key) => return KeyMatch::Complete(binding.action),
+                None => {}
+            }
+        }
+        if prefix {
+            KeyMatch::Prefix
+        } else {
+            KeyMatch::None
+        }
+    }
+
+    /// The action bound to `key` pressed after the chord prefix `prefix`.
+    pub fn match_chord(&self, prefix: &KeyInput, key: &KeyInput) -> Option<KeyAction> {
+        self.bindings
+            .iter()
+            .find(|b| b.prefix.as_ref().is_some_and(|p| p.matches(prefix)) && b.last.matches(key))
+            .map(|b| b.action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_keymap() -> Keymap {
+        let mut keymap = Keymap::new();
+        keymap.bind("[ctrl][6]", KeyAction::ToggleLookback).unwrap();
+        keymap.bind("[ctrl][5]", KeyAction::TogglePeek).unwrap();
+        keymap.bind("[ctrl][c]", KeyAction::ExitLookback).unwrap();
+        keymap
+    }
+
+    fn press(bytes: &[u8]) -> KeyInput {
+        KeyInput {
+            bytes: bytes.to_vec(),
+            key: input_decoder::decode_key(bytes),
+            paste: false,
+        }
+    }
+
+    #[test]
+    fn test_match_legacy_keys() {
+        let keymap = default_keymap();
+        assert_eq!(
+            keymap.match_key(&press(&[0x1E])),
+            KeyMatch::Complete(KeyAction::ToggleLookback)
+        );
+        assert_eq!(
+            keymap.match_key(&press(&[0x03])),
+            KeyMatch::Complete(KeyAction::ExitLookback)
+        );
+        assert_eq!(keymap.match_key(&press(b"a")), KeyMatch::None);
+    }
+
+    #[test]
+    fn test_match_kitty_keys() {
+        let keymap = default_keymap();
+        assert_eq!(
+            keymap.match_key(&press(b"\x1b[54;5u")),
+            KeyMatch::Complete(KeyAction::ToggleLookback)
+        );
+        assert_eq!(
+            keymap.match_key(&press(b"\x1b[99;5u")),
+            KeyMatch::Complete(KeyAction::ExitLookback)
+        );
+        // CapsLock doesn't get in the way
+        assert_eq!(
+            keymap.match_key(&press(b"\x1b[99;69u")),
+            KeyMatch::Complete(KeyAction::ExitLookback)
+        );
+        assert_eq!(keymap.match_key(&press(b"\x1b[55;5u")), KeyMatch::None);
+        // Super+C is a different key
+        assert_eq!(keymap.match_key(&press(b"\x1b[99;9u")), KeyMatch::None);
+    }
+
+    #[test]
+    fn test_legacy_alias_only_for_legacy_input() {
+        let mut keymap = Keymap::new();
+        keymap
+            .bind("[ctrl][shift][a]", KeyAction::ClearHistory)
+            .unwrap();
+        // Legacy terminals send Ctrl+Shift+A as plain Ctrl+A
+        assert_eq!(
+            keymap.match_key(&press(&[0x01])),
+            KeyMatch::Complete(KeyAction::ClearHistory)
+        );
+        assert_eq!(
+            keymap.match_key(&press(b"\x1b[97;6u")),
+            KeyMatch::Complete(KeyAction::ClearHistory)
+        );
+        // Kitty can tell Ctrl+A apart
+        assert_eq!(keymap.match_key(&press(b"\x1b[97;5u")), KeyMatch::None);
+    }
+
+    #[test]
+    fn test_several_keys_per_action() {
+        let mut keymap = default_keymap();
+        keymap.bind("[f12]", KeyAction::ToggleLookback).unwrap();
+        assert_eq!(
+            keymap.match_key(&press(b"\x1b[24~")),
+            KeyMatch::Complete(KeyAction::ToggleLookback)
+        );
+        assert_eq!(
+            keymap.match_key(&press(&[0x1E])),
+            KeyMatch::Complete(KeyAction::ToggleLookback)
+        );
+        assert_eq!(keymap.key_for(KeyAction::ToggleLookback), Some("[ctrl][6]"));
+    }
+
+    #[test]
+    fn test_rebinding_a_key_replaces_it() {
+        let mut keymap = default_keymap();
+        keymap.bind("[ctrl][c]", KeyAction::ClearHistory).unwrap();
+        assert!(!keymap.is_bound(KeyAction::ExitLookback));
+        assert_eq!(
+            keymap.match_key(&press(&[0x03])),
+            KeyMatch::Complete(KeyAction::ClearHistory)
+        );
+    }
+
+    #[test]
+    fn test_invalid_key_is_rejected() {
+        let mut keymap = Keymap::new();
+        assert!(keymap.bind("ctrl+6", KeyAction::ToggleLookback).is_err());
+        assert!(!keymap.is_bound(KeyAction::ToggleLookback));
+    }
+
+    #[test]
+    fn test_chord() {
+        let mut keymap = default_keymap();
+        keymap
+            .bind("[ctrl][a] [l]", KeyAction::ToggleLookback)
+            .unwrap();
+        keymap
+            .bind("[ctrl][a] [f5]", KeyAction::ForceRedraw)
+            .unwrap();
+        assert_eq!(keymap.match_key(&press(&[0x01])), KeyMatch::Prefix);
+        assert_eq!(keymap.match_key(&press(b"\x1b[97;5u")), KeyMatch::Prefix);
+        assert_eq!(
+            keymap.match_chord(&press(&[0x01]), &press(b"l")),
+            Some(KeyAction::ToggleLookback)
+        );
+        assert_eq!(
+            keymap.match_chord(&press(b"\x1b[97;5u"), &press(b"\x1b[108u")),
+            Some(KeyAction::ToggleLookback)
+        );
+        assert_eq!(
+            keymap.match_chord(&press(&[0x01]), &press(b"\x1b[15~")),
+            Some(KeyAction::ForceRedraw)
+        );
+        assert_eq!(keymap.match_chord(&press(&[0x01]), &press(b"x")), None);
+        // The key after the prefix is only special after the prefix
+        assert_eq!(keymap.match_key(&press(b"l")), KeyMatch::None);
+        assert!(
+            keymap
+                .bind("[ctrl][a] [b] [c]", KeyAction::DumpHistory)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_guarded_keys() {
+        let mut keymap = default_keymap();
+        keymap.guard("[ctrl][c]").unwrap();
+        keymap.guard("[ctrl][d]").unwrap();
+        assert!(keymap.is_guarded(&press(&[0x03])));
+        assert!(keymap.is_guarded(&press(b"\x1b[99;5u")));
+        assert!(keymap.is_guarded(&press(&[0x04])));
+        assert!(!keymap.is_guarded(&press(b"c")));
+        // Guarding a key doesn't unbind it
+        assert_eq!(
+            keymap.match_key(&press(&[0x03])),
+            KeyMatch::Complete(KeyAction::ExitLookback)
+        );
+        assert!(keymap.guard("[ctrl][a] [c]").is_err());
+    }
+}
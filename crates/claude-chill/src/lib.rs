@@ -1,8 +1,23 @@
 pub mod config;
 pub mod escape_filter;
 pub mod escape_sequences;
+pub mod export;
+pub mod frame_dedup;
 pub mod history_filter;
+pub mod history_generations;
+pub mod hyperlinks;
+pub mod input_decoder;
+pub mod input_queue;
 pub mod key_parser;
+pub mod keyboard_modes;
+pub mod keymap;
 pub mod line_buffer;
+pub mod linkify;
+pub mod lookback_cache;
+pub mod lz;
+pub mod pager;
 pub mod proxy;
 pub mod redraw_throttler;
+pub mod screen_history;
+pub mod sessions;
+pub mod side_channel;
@@ -1,34 +1,144 @@
+use crate::lz;
+use log::warn;
+use memchr::memmem;
 use std::collections::VecDeque;
+use termwiz::cell::unicode_column_width;
+
+/// Complete lines beyond this many bytes are compressed into cold blocks.
+const HOT_BYTES: usize = 256 * 1024;
+/// Target uncompressed size of a cold block.
+const BLOCK_BYTES: usize = 64 * 1024;
+/// Lines longer than this are stored without collapsing `\r` overwrites.
+const MAX_COLLAPSE_BYTES: usize = 64 * 1024;
+
+/// Older lines, compressed together. Each line is stored with its newline.
+struct ColdBlock {
+    compressed: Vec<u8>,
+    line_lengths: Vec<u32>,
+}
+
+impl ColdBlock {
+    /// Append up to `take_lines` lines after the first `skip_lines`.
+    fn decompress_into(&self, skip_lines: usize, take_lines: usize, output: &mut Vec<u8>) {
+        let end_line = skip_lines
+            .saturating_add(take_lines)
+            .min(self.line_lengths.len());
+        let byte_offset = |lines: usize| -> usize {
+            self.line_lengths[..lines]
+                .iter()
+                .map(|&len| len as usize + 1)
+                .sum()
+        };
+        let (start, end) = (byte_offset(skip_lines), byte_offset(end_line));
+        let mut block = Vec::new();
+        match lz::decompress(&self.compressed, &mut block) {
+            Ok(_) => output.extend_from_slice(&block[start.min(block.len())..end.min(block.len())]),
+            Err(e) => warn!(
+                "history block of {} lines is corrupt, skipping it: {}",
+                end_line - skip_lines,
+                e
+            ),
+        }
+    }
+}
 
 pub struct LineBuffer {
+    cold: VecDeque<ColdBlock>,
+    /// Lines already evicted from the front cold block
+    cold_skip: usize,
+    cold_lines: usize,
     lines: VecDeque<Vec<u8>>,
+    hot_bytes: usize,
     current_line: Vec<u8>,
+    /// The current line ran past the length limit and lost its end
+    truncated: bool,
     max_lines: usize,
+    max_bytes: usize,
+    hot_limit: usize,
+    block_bytes: usize,
     cached_bytes: usize,
 }
 
 impl LineBuffer {
     pub fn new(max_lines: usize) -> Self {
         Self {
+            cold: VecDeque::new(),
+            cold_skip: 0,
+            cold_lines: 0,
             lines: VecDeque::new(),
+            hot_bytes: 0,
             current_line: Vec::new(),
+            truncated: false,
             max_lines,
+            max_bytes: usize::MAX,
+            hot_limit: HOT_BYTES,
+            block_bytes: BLOCK_BYTES,
             cached_bytes: 0,
         }
     }
 
+    /// Also evict old lines once history totals more than `max_bytes`, and
+    /// keep at most half of that of any one line. 0 means no byte limit.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = if max_bytes == 0 {
+            usize::MAX
+        } else {
+            max_bytes
+        };
+        self
+    }
+
     pub fn push_byte(&mut self, byte: u8) {
         if byte == b'\n' {
+            if self.current_line.last() != Some(&b'\r') {
+                self.collapse_current_line();
+            }
+            if std::mem::take(&mut self.truncated) && self.current_line.contains(&0x1b) {
+                // The cut may have dropped the reset for a style the line
+                // opened, which would carry over into every later line
+                self.current_line.extend_from_slice(b"\x1b[0m");
+            }
             let line = std::mem::take(&mut self.current_line);
             self.cached_bytes += line.len() + 1;
+            self.hot_bytes += line.len() + 1;
             self.lines.push_back(line);
-            if self.lines.len() > self.max_lines
-                && let Some(removed) = self.lines.pop_front()
-            {
-                self.cached_bytes -= removed.len() + 1;
+            self.evict_to_limits();
+            if self.hot_bytes > self.hot_limit {
+                self.compress_hot(self.hot_limit);
             }
         } else {
+            if byte == b'\r' {
+                self.collapse_current_line();
+            }
+            // The rest of a line too long to keep is dropped, so it can't
+            // push out all other history
+            if self.current_line.len() >= self.max_bytes / 2 {
+                self.truncated = true;
+                return;
+            }
             self.current_line.push(byte);
+            self.evict_to_limits();
+        }
+    }
+
+    fn evict_to_limits(&mut self) {
+        while self.complete_lines() > self.max_lines
+            || (self.total_bytes() > self.max_bytes && self.complete_lines() > 0)
+        {
+            self.evict_front();
+        }
+    }
+
+    /// Drop the parts of the current line that later `\r` overwrites hide,
+    /// so spinners and progress bars keep only their latest state.
+    fn collapse_current_line(&mut self) {
+        if self.current_line.len() > MAX_COLLAPSE_BYTES {
+            return;
+        }
+        if let Some((start, sgr)) = visible_start(&self.current_line) {
+            let mut line = sgr;
+            line.extend_from_slice(&self.current_line[start..]);
+            self.current_line = line;
         }
     }
 
@@ -39,44 +149,234 @@ impl LineBuffer {
     }
 
     pub fn clear(&mut self) {
+        self.cold.clear();
+        self.cold_skip = 0;
+        self.cold_lines = 0;
         self.lines.clear();
+        self.hot_bytes = 0;
         self.current_line.clear();
+        self.truncated = false;
         self.cached_bytes = 0;
     }
 
+    /// Compress every complete line, for buffers that are only kept around
+    /// to be read later.
+    pub fn compact(&mut self) {
+        self.compress_hot(0);
+    }
+
     pub fn line_count(&self) -> usize {
-        self.lines.len() + if self.current_line.is_empty() { 0 } else { 1 }
+        self.complete_lines() + if self.current_line.is_empty() { 0 } else { 1 }
+    }
+
+    /// Bytes pushed since the last newline.
+    pub fn partial_line(&self) -> &[u8] {
+        &self.current_line
     }
 
     pub fn total_bytes(&self) -> usize {
         self.cached_bytes + self.current_line.len()
     }
 
+    /// Bytes held in memory, with cold blocks at their compressed size.
+    pub fn resident_bytes(&self) -> usize {
+        let cold: usize = self
+            .cold
+            .iter()
+            .map(|block| block.compressed.len() + block.line_lengths.len() * 4)
+            .sum();
+        cold + self.hot_bytes + self.current_line.len()
+    }
+
     pub fn append_last_n_lines(&self, n: usize, output: &mut Vec<u8>) {
         let total_lines = self.line_count();
         let lines_to_skip = total_lines.saturating_sub(n);
-        let completed_lines_to_skip = lines_to_skip.min(self.lines.len());
+        let completed_lines_to_skip = lines_to_skip.min(self.complete_lines());
 
-        for line in self.lines.iter().skip(completed_lines_to_skip) {
-            output.extend_from_slice(line);
-            output.push(b'\n');
-        }
+        self.append_complete_lines(completed_lines_to_skip, usize::MAX, output);
         if !self.current_line.is_empty() && lines_to_skip < total_lines {
             output.extend_from_slice(&self.current_line);
         }
     }
 
+    /// Append `count` lines starting at line `start`, oldest first. The
+    /// partial line counts as the last line.
+    pub fn append_lines(&self, start: usize, count: usize, output: &mut Vec<u8>) {
+        let complete = self.complete_lines();
+        if start < complete {
+            self.append_complete_lines(start, count, output);
+        }
+        if start.saturating_add(count) > complete && start <= complete {
+            output.extend_from_slice(&self.current_line);
+        }
+    }
+
     pub fn append_all(&self, output: &mut Vec<u8>) {
-        for line in &self.lines {
+        self.append_complete_lines(0, usize::MAX, output);
+        if !self.current_line.is_empty() {
+            output.extend_from_slice(&self.current_line);
+        }
+    }
+
+    fn complete_lines(&self) -> usize {
+        self.cold_lines + self.lines.len()
+    }
+
+    /// Append up to `take` complete lines after skipping the oldest `skip`
+    /// of them, decompressing only the cold blocks that are needed.
+    fn append_complete_lines(&self, mut skip: usize, mut take: usize, output: &mut Vec<u8>) {
+        for (index, block) in self.cold.iter().enumerate() {
+            if take == 0 {
+                return;
+            }
+            let first = if index == 0 { self.cold_skip } else { 0 };
+            let live = block.line_lengths.len() - first;
+            if skip >= live {
+                skip -= live;
+                continue;
+            }
+            block.decompress_into(first + skip, take, output);
+            take = take.saturating_sub(live - skip);
+            skip = 0;
+        }
+        for line in self.lines.iter().skip(skip).take(take) {
             output.extend_from_slice(line);
             output.push(b'\n');
         }
-        if !self.current_line.is_empty() {
-            output.extend_from_slice(&self.current_line);
+    }
+
+    fn evict_front(&mut self) {
+        if let Some(block) = self.cold.front() {
+            let len = block.line_lengths[self.cold_skip] as usize;
+            self.cached_bytes -= len + 1;
+            self.cold_lines -= 1;
+            self.cold_skip += 1;
+            if self.cold_skip == block.line_lengths.len() {
+                self.cold.pop_front();
+                self.cold_skip = 0;
+            }
+        } else if let Some(removed) = self.lines.pop_front() {
+            self.cached_bytes -= removed.len() + 1;
+            self.hot_bytes -= removed.len() + 1;
+        }
+    }
+
+    /// Move the oldest hot lines into cold blocks until at most `keep_bytes`
+    /// of hot lines remain.
+    fn compress_hot(&mut self, keep_bytes: usize) {
+        while self.hot_bytes > keep_bytes && !self.lines.is_empty() {
+            let mut raw = Vec::with_capacity(self.block_bytes);
+            let mut line_lengths = Vec::new();
+            while raw.len() < self.block_bytes
+                && let Some(line) = self.lines.pop_front()
+            {
+                self.hot_bytes -= line.len() + 1;
+                line_lengths.push(line.len() as u32);
+                raw.extend_from_slice(&line);
+                raw.push(b'\n');
+            }
+            self.cold_lines += line_lengths.len();
+            self.cold.push_back(ColdBlock {
+                compressed: lz::compress(&raw),
+                line_lengths,
+            });
         }
     }
 }
 
+/// Find the last `\r` in a line after which everything before it is hidden,
+/// either overwritten by text at least as wide or erased with `CSI K`.
+/// Returns its index and the SGR sequences still in effect there.
+///
+/// Only text, SGR and `CSI K` are understood. Anything else that moves the
+/// cursor (tabs, backspace, other escapes) stops the search, since earlier
+/// text could then still be visible.
+fn visible_start(line: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let mut result = None;
+    // SGR sequences in effect, and at the start of the current segment
+    let mut sgr = Vec::new();
+    let mut segment_sgr = Vec::new();
+    let mut segment_start = 0;
+    // Widest text before the current segment, and this segment's width
+    let mut extent = 0;
+    let mut col = 0;
+    let mut erased = false;
+
+    let mut i = 0;
+    loop {
+        let end = i == line.len();
+        if end || line[i] == b'\r' {
+            if segment_start > 0 && (erased || col >= extent) {
+                result = Some((segment_start, segment_sgr.clone()));
+                extent = col;
+            } else {
+                extent = extent.max(col);
+            }
+            if end {
+                return result;
+            }
+            segment_start = i;
+            segment_sgr.clone_from(&sgr);
+            col = 0;
+            erased = false;
+            i += 1;
+            continue;
+        }
+
+        match line[i] {
+            0x1b => {
+                let Some((params, final_byte, len)) = parse_csi(&line[i..]) else {
+                    return result;
+                };
+                match (final_byte, params) {
+                    (b'm', b"" | b"0") => sgr.clear(),
+                    (b'm', _) => {
+                        if params.starts_with(b"0;") {
+                            sgr.clear();
+                        }
+                        push_sgr(&mut sgr, &line[i..i + len]);
+                    }
+                    (b'K', b"" | b"0" | b"2") => erased = true,
+                    _ => return result,
+                }
+                i += len;
+            }
+            byte if byte < 0x20 || byte == 0x7f => return result,
+            _ => {
+                let run = line[i..]
+                    .iter()
+                    .position(|&b| b < 0x20 || b == 0x7f)
+                    .map_or(line.len(), |pos| i + pos);
+                col += unicode_column_width(&String::from_utf8_lossy(&line[i..run]), None);
+                i = run;
+            }
+        }
+    }
+}
+
+/// Parse a CSI sequence at the start of `data` into its parameter bytes,
+/// final byte and total length.
+fn parse_csi(data: &[u8]) -> Option<(&[u8], u8, usize)> {
+    if data.get(1) != Some(&b'[') {
+        return None;
+    }
+    let params_len = data[2..].iter().position(|b| !(0x30..=0x3f).contains(b))?;
+    let final_byte = *data.get(2 + params_len)?;
+    if !(0x40..=0x7e).contains(&final_byte) {
+        return None;
+    }
+    Some((&data[2..2 + params_len], final_byte, 3 + params_len))
+}
+
+/// Append an SGR sequence, dropping an earlier identical one so repeated
+/// styling in each overwrite doesn't accumulate.
+fn push_sgr(sgr: &mut Vec<u8>, sequence: &[u8]) {
+    if let Some(pos) = memmem::find(sgr, sequence) {
+        sgr.drain(pos..pos + sequence.len());
+    }
+    sgr.extend_from_slice(sequence);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -201,6 +501,165 @@ mod tests {
         );
     }
 
+    fn compressing(max_lines: usize) -> LineBuffer {
+        let mut buf = LineBuffer::new(max_lines);
+        buf.hot_limit = 512;
+        buf.block_bytes = 256;
+        buf
+    }
+
+    #[test]
+    fn test_max_bytes_eviction() {
+        let mut buf = LineBuffer::new(100).with_max_bytes(12);
+        buf.push_bytes(b"aaaa\nbbbb\ncccc\n");
+        assert_eq!(get_all(&buf), b"bbbb\ncccc\n");
+        assert_eq!(buf.total_bytes(), 10);
+        // The line being written counts too
+        buf.push_bytes(b"ddd");
+        assert_eq!(get_all(&buf), b"cccc\nddd");
+        // A line over half the limit is cut short instead of evicting
+        // everything
+        buf.push_bytes(b"0123456789abcdef\n");
+        assert_eq!(get_all(&buf), b"cccc\nddd012\n");
+        buf.push_bytes(b"ee\n");
+        assert_eq!(get_all(&buf), b"ddd012\nee\n");
+        assert_eq!(buf.total_bytes(), 10);
+    }
+
+    #[test]
+    fn test_truncated_styled_line_is_reset() {
+        let mut buf = LineBuffer::new(100).with_max_bytes(24);
+        buf.push_bytes(b"\x1b[31mred text that goes on\x1b[0m\nplain\n");
+        assert_eq!(get_all(&buf), b"\x1b[31mred tex\x1b[0m\nplain\n");
+        // Lines that fit keep their bytes as written
+        buf.clear();
+        buf.push_bytes(b"\x1b[1mbold\x1b[0m\n");
+        assert_eq!(get_all(&buf), b"\x1b[1mbold\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_cold_blocks_round_trip() {
+        let mut buf = compressing(1000);
+        let mut expected = Vec::new();
+        for i in 0..200 {
+            let line = format!("\x1b[32mline {}\x1b[0m\r\n", i);
+            buf.push_bytes(line.as_bytes());
+            expected.extend_from_slice(line.as_bytes());
+        }
+        buf.push_bytes(b"partial");
+        expected.extend_from_slice(b"partial");
+
+        assert!(!buf.cold.is_empty());
+        assert!(buf.hot_bytes <= 512);
+        assert_eq!(buf.line_count(), 201);
+        assert_eq!(buf.total_bytes(), expected.len());
+        assert_eq!(get_all(&buf), expected);
+        assert_eq!(get_last_n(&buf, 2), b"\x1b[32mline 199\x1b[0m\r\npartial");
+        assert!(buf.resident_bytes() < buf.total_bytes());
+    }
+
+    #[test]
+    fn test_eviction_from_cold_blocks() {
+        let mut buf = compressing(100);
+        for i in 0..500 {
+            buf.push_bytes(format!("line {:03} of the test\n", i).as_bytes());
+        }
+        assert!(!buf.cold.is_empty());
+        assert!(buf.cold_skip > 0);
+        assert_eq!(buf.line_count(), 100);
+        let all = get_all(&buf);
+        assert!(all.starts_with(b"line 400 of the test\n"));
+        assert!(all.ends_with(b"line 499 of the test\n"));
+        assert_eq!(buf.total_bytes(), all.len());
+        assert_eq!(get_last_n(&buf, 90), all[all.len() - 90 * 21..]);
+    }
+
+    #[test]
+    fn test_append_lines_range() {
+        let mut buf = compressing(100);
+        for i in 0..500 {
+            buf.push_bytes(format!("line {:03} of the test\n", i).as_bytes());
+        }
+        buf.push_bytes(b"partial");
+        let range = |start, count| {
+            let mut result = Vec::new();
+            buf.append_lines(start, count, &mut result);
+            result
+        };
+        // Spans the evicted front block, later cold blocks and hot lines
+        let all = get_all(&buf);
+        assert_eq!(range(0, 101), all);
+        assert_eq!(range(10, 3), all[10 * 21..13 * 21]);
+        assert_eq!(
+            range(98, 5),
+            b"line 498 of the test\nline 499 of the test\npartial"
+        );
+        assert_eq!(range(100, 1), b"partial");
+        assert!(range(101, 1).is_empty());
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut buf = LineBuffer::new(100);
+        buf.push_bytes(b"one\ntwo\nthree");
+        buf.compact();
+        assert!(buf.lines.is_empty());
+        assert_eq!(buf.line_count(), 3);
+        assert_eq!(get_all(&buf), b"one\ntwo\nthree");
+        buf.clear();
+        assert_eq!(get_all(&buf), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_carriage_return_overwrites_collapse() {
+        let mut buf = LineBuffer::new(10);
+        buf.push_bytes(b"50%\r75%\r100%\r\n");
+        assert_eq!(get_all(&buf), b"\r100%\r\n");
+
+        let mut buf = LineBuffer::new(10);
+        for frame in ["\u{280b}", "\u{2819}", "\u{2839}"] {
+            buf.push_bytes(format!("\r\x1b[K{} working", frame).as_bytes());
+        }
+        // The latest overwrite is only checked once the next one starts
+        assert_eq!(
+            get_all(&buf),
+            "\r\x1b[K\u{2819} working\r\x1b[K\u{2839} working".as_bytes()
+        );
+        buf.push_bytes(b"\r\x1b[2Kdone\r\n");
+        assert_eq!(get_all(&buf), b"\r\x1b[2Kdone\r\n");
+    }
+
+    #[test]
+    fn test_partial_overwrites_are_kept() {
+        let mut buf = LineBuffer::new(10);
+        buf.push_bytes(b"hello world\rbye\r\n");
+        assert_eq!(get_all(&buf), b"hello world\rbye\r\n");
+
+        // Wide characters take two columns each
+        let mut buf = LineBuffer::new(10);
+        buf.push_bytes("\u{65e5}\u{672c}\rab\n".as_bytes());
+        assert_eq!(get_all(&buf), "\u{65e5}\u{672c}\rab\n".as_bytes());
+
+        // A tab moves the cursor in ways that aren't tracked
+        let mut buf = LineBuffer::new(10);
+        buf.push_bytes(b"a\tb\rxyz\n");
+        assert_eq!(get_all(&buf), b"a\tb\rxyz\n");
+    }
+
+    #[test]
+    fn test_overwrite_keeps_active_style() {
+        let mut buf = LineBuffer::new(10);
+        buf.push_bytes(b"\x1b[1m\x1b[31m50%\r100%\x1b[0m\r\n");
+        assert_eq!(get_all(&buf), b"\x1b[1m\x1b[31m\r100%\x1b[0m\r\n");
+
+        let mut buf = LineBuffer::new(10);
+        for i in 0..100 {
+            buf.push_bytes(format!("\r\x1b[32m{:>3}%", i).as_bytes());
+        }
+        buf.push_bytes(b"\n");
+        assert_eq!(get_all(&buf), b"\x1b[32m\r\x1b[32m 99%\n");
+    }
+
     #[test]
     fn test_crlf_preserved_in_last_n() {
         let mut buf = LineBuffer::new(10);
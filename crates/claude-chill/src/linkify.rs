@@ -0,0 +1,327 @@
+//! Links for URLs and file references in the text on screen.
+//!
+//! With `linkify = true`, every rendered frame is scanned for `http(s)://`
+//! URLs and `path/to/file.rs:123` references, and the ones found are drawn
+//! as OSC 8 links along with the child's own. File references only become
+//! links if the file exists, resolved against the child's working
+//! directory, and point at `file://` URIs with the line as the fragment.
+//!
+//! Lines and file lookups from the last frame are remembered, so only lines
+//! that changed are scanned again.
+
+use crate::hyperlinks::LinkRun;
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Trailing characters that end a sentence rather than a URL.
+const URL_TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"'];
+
+pub struct Linkifier {
+    url: Regex,
+    file: Regex,
+    /// The directory the cached file references were resolved against
+    cwd: PathBuf,
+    /// Links in each line of the last frame, by the line's text
+    lines: HashMap<String, Rc<[Link]>>,
+    /// Files the references in lines scanned for the last frame resolved to
+    files: HashMap<String, Option<PathBuf>>,
+}
+
+/// A link found in a line, by where its text is in the line.
+struct Link {
+    text: Range<usize>,
+    uri: String,
+}
+
+/// A soft-wrapped line of the screen as text, with where each cell's text
+/// starts in it.
+struct ScreenLine {
+    text: String,
+    cells: Vec<(usize, u16, u16)>,
+}
+
+impl Linkifier {
+    pub fn new() -> Self {
+        Self {
+            url: Regex::new(r#"https?://[^\s<>"'`]+"#).unwrap(),
+            file: Regex::new(
+                r#"(?:^|[\s(\[{<'"`=,])((?:~|\.\.?)?/?[\w.+-]+(?:/[\w.+-]+)*):(\d+)(?::\d+)?"#,
+            )
+            .unwrap(),
+            cwd: PathBuf::new(),
+            lines: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Links for the URLs and existing file references on `screen`.
+    pub fn find(&mut self, screen: &vt100::Screen, cwd: &Path) -> Vec<LinkRun> {
+        if self.cwd != cwd {
+            self.cwd = cwd.to_path_buf();
+            self.lines.clear();
+            self.files.clear();
+        }
+        let mut lines = HashMap::new();
+        let mut files = HashMap::new();
+        let mut runs = Vec::new();
+        for line in screen_lines(screen) {
+            let links = match self.lines.get(&line.text) {
+                Some(links) => links.clone(),
+                None => Rc::from(self.scan(&line.text, &mut files)),
+            };
+            for link in links.iter() {
+                line.push_runs(link.text.clone(), &link.uri, &mut runs);
+            }
+            lines.insert(line.text, links);
+        }
+        self.lines = lines;
+        self.files = files;
+        runs
+    }
+
+    /// The links in one line of text. Files are looked up in the cache from
+    /// the last frame first, and every lookup is recorded in `files`.
+    fn scan(&self, text: &str, files: &mut HashMap<String, Option<PathBuf>>) -> Vec<Link> {
+        let mut links = Vec::new();
+        for m in self.url.find_iter(text) {
+            let url = trim_url(m.as_str());
+            if url.len() > "https://".len() {
+                links.push(Link {
+                    text: m.start()..m.start() + url.len(),
+                    uri: url.to_string(),
+                });
+            }
+        }
+        let urls = links.len();
+        for caps in self.file.captures_iter(text) {
+            let (Some(path), Some(line_number)) = (caps.get(1), caps.get(2)) else {
+                continue;
+            };
+            let range = path.start()..caps.get(0).map_or(path.end(), |m| m.end());
+            if links[..urls]
+                .iter()
+                .any(|u| u.text.start < range.end && range.start < u.text.end)
+            {
+                continue;
+            }
+            let file = match files.get(path.as_str()).or(self.files.get(path.as_str())) {
+                Some(file) => file.clone(),
+                None => resolve(path.as_str(), &self.cwd),
+            };
+            files.insert(path.as_str().to_string(), file.clone());
+            let Some(file) = file else {
+                continue;
+            };
+            links.push(Link {
+                text: range,
+                uri: format!("file://{}#{}", encode_path(&file), line_number.as_str()),
+            });
+        }
+        links
+    }
+}
+
+impl Default for Linkifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScreenLine {
+    /// Add the runs covering `text` in this line, one per row it spans.
+    fn push_runs(&self, text: Range<usize>, uri: &str, runs: &mut Vec<LinkRun>) {
+        let link: Rc<str> = Rc::from(format!(";{}", uri));
+        for &(_, row, col) in self.cells.iter().filter(|c| text.contains(&c.0)) {
+            match runs.last_mut() {
+                Some(run) if run.row == row && run.cols.end == col && run.link == link => {
+                    run.cols.end = col + 1;
+                }
+                _ => runs.push(LinkRun {
+                    row,
+                    cols: col..col + 1,
+                    link: link.clone(),
+                }),
+            }
+        }
+    }
+}
+
+/// The screen's rows, with soft-wrapped rows joined into one line.
+fn screen_lines(screen: &vt100::Screen) -> Vec<ScreenLine> {
+    let (rows, cols) = screen.size();
+    let mut lines = Vec::new();
+    let mut line = ScreenLine {
+        text: String::new(),
+        cells: Vec::new(),
+    };
+    for row in 0..rows {
+        for col in 0..cols {
+            let Some(cell) = screen.cell(row, col) else {
+                continue;
+            };
+            if cell.is_wide_continuation() {
+                continue;
+            }
+            line.cells.push((line.text.len(), row, col));
+            if cell.has_contents() {
+                line.text.push_str(cell.contents());
+            } else {
+                line.text.push(' ');
+            }
+        }
+        if !screen.row_wrapped(row) {
+            lines.push(std::mem::replace(
+                &mut line,
+                ScreenLine {
+                    text: String::new(),
+                    cells: Vec::new(),
+                },
+            ));
+        }
+    }
+    if !line.cells.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Drop trailing punctuation, and closing parens that weren't opened in the
+/// URL, e.g. `(see https://example.com/a_(b)).`
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let trimmed = url.trim_end_matches(URL_TRAILING);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(rest) if trimmed.matches('(').count() < trimmed.matches(')').count() => rest,
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+/// The absolute path of an existing file `path` refers to.
+fn resolve(path: &str, cwd: &Path) -> Option<PathBuf> {
+    if !path.contains(['/', '.']) {
+        return None;
+    }
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()?.join(rest),
+        None => cwd.join(path),
+    };
+    let path: PathBuf = path
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect();
+    path.is_file().then_some(path)
+}
+
+/// Percent-encode a path for a `file://` URI.
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &b in path.as_os_str().as_encoded_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn screen(rows: u16, cols: u16, text: &str) -> vt100::Parser {
+        let mut parser = vt100::Parser::new(rows, cols, 0);
+        parser.process(text.as_bytes());
+        parser
+    }
+
+    fn links(runs: &[LinkRun]) -> Vec<(u16, Range<u16>, &str)> {
+        runs.iter()
+            .map(|r| (r.row, r.cols.clone(), r.link.as_ref()))
+            .collect()
+    }
+
+    #[test]
+    fn test_finds_urls() {
+        let parser = screen(
+            3,
+            60,
+            "see https://example.com/a_(b)). or (http://x.io/y)\r\n",
+        );
+        let runs = Linkifier::new().find(parser.screen(), Path::new("/"));
+        assert_eq!(
+            links(&runs),
+            vec![
+                (0, 4..29, ";https://example.com/a_(b)"),
+                (0, 36..49, ";http://x.io/y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_url_wrapped_across_rows() {
+        let parser = screen(3, 20, "open https://example.com/long/path now");
+        let runs = Linkifier::new().find(parser.screen(), Path::new("/"));
+        let link = ";https://example.com/long/path";
+        assert_eq!(links(&runs), vec![(0, 5..20, link), (1, 0..14, link)]);
+    }
+
+    #[test]
+    fn test_finds_existing_files() {
+        let dir = std::env::temp_dir().join(format!("claude-chill-linkify-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main rs.rs"), "").unwrap();
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+
+        let parser = screen(
+            3,
+            60,
+            "Edited `./src/lib.rs:12:5`, src/gone.rs:3 and 12:30\r\n",
+        );
+        let runs = Linkifier::new().find(parser.screen(), &dir);
+        let uri = format!(";file://{}/src/lib.rs#12", dir.display());
+        assert_eq!(links(&runs), vec![(0, 8..25, uri.as_str())]);
+        assert_eq!(
+            encode_path(&dir.join("src/main rs.rs")),
+            format!("{}/src/main%20rs.rs", dir.display())
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_unchanged_lines_not_looked_up_again() {
+        let dir =
+            std::env::temp_dir().join(format!("claude-chill-linkify-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.rs"), "").unwrap();
+
+        let mut linkifier = Linkifier::new();
+        let mut parser = screen(3, 40, "a.rs:1\r\n");
+        assert_eq!(linkifier.find(parser.screen(), &dir).len(), 1);
+        // Cached from the last frame, so the missing file is still linked
+        fs::remove_file(dir.join("a.rs")).unwrap();
+        parser.process(b"b.rs:2\r\n");
+        let runs = linkifier.find(parser.screen(), &dir);
+        assert_eq!(links(&runs).len(), 1);
+        assert_eq!(linkifier.files.len(), 1);
+        // Lines that went off screen are forgotten
+        parser.process(b"\x1b[2J");
+        assert!(linkifier.find(parser.screen(), &dir).is_empty());
+        assert_eq!(linkifier.lines.len(), 1);
+        assert!(linkifier.files.is_empty());
+        // A new directory starts over
+        parser.process(b"\x1b[Ha.rs:1");
+        assert!(linkifier.find(parser.screen(), &dir.join("x")).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,236 @@
+//! Child output held back while lookback mode is open.
+//!
+//! Output is kept in memory up to a limit. The proxy normally empties the
+//! cache by processing it in the background once it is full, but output that
+//! can't be processed without drawing on the terminal (the alternate screen)
+//! spills to an unlinked temporary file instead, so memory stays bounded
+//! however long lookback is open.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::PathBuf;
+
+/// Spilled output is read back in chunks of this size.
+const REPLAY_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Attempts at finding an unused name for the spill file.
+const SPILL_NAME_ATTEMPTS: usize = 16;
+
+pub struct LookbackCache {
+    memory: Vec<u8>,
+    max_bytes: usize,
+    spill: Option<File>,
+    spilled_bytes: usize,
+    /// How much of the spill file has been read back
+    read_bytes: usize,
+}
+
+impl LookbackCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            memory: Vec::new(),
+            max_bytes,
+            spill: None,
+            spilled_bytes: 0,
+            read_bytes: 0,
+        }
+    }
+
+    /// Add output, to the spill file if one has been started.
+    pub fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.spill.as_mut() {
+            Some(file) => {
+                file.write_all(data)?;
+                self.spilled_bytes += data.len();
+            }
+            None => self.memory.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    /// Whether the in-memory part has grown past the limit.
+    pub fn is_full(&self) -> bool {
+        self.spill.is_none() && self.memory.len() > self.max_bytes
+    }
+
+    pub fn is_spilling(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spilled_bytes - self.read_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take the in-memory output, e.g. to process it in the background.
+    pub fn take_memory(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.memory)
+    }
+
+    /// Move the in-memory output to a temporary file and send all further
+    /// output there. The file is only readable by the user and is unlinked
+    /// right away, so it disappears with the cache even if the proxy is
+    /// killed.
+    pub fn spill(&mut self) -> io::Result<()> {
+        if self.spill.is_some() {
+            return Ok(());
+        }
+        let mut file = create_spill_file()?;
+        file.write_all(&self.memory)?;
+        self.spilled_bytes = self.memory.len();
+        self.memory = Vec::new();
+        self.spill = Some(file);
+        Ok(())
+    }
+
+    /// Take the next part of the cached output, in order. Spilled output
+    /// comes a chunk at a time, so it can be written out a little at a time
+    /// while more output is added behind it.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(file) = &self.spill
+            && self.read_bytes < self.spilled_bytes
+        {
+            let len = (self.spilled_bytes - self.read_bytes).min(REPLAY_CHUNK_BYTES);
+            let mut chunk = vec![0u8; len];
+            file.read_exact_at(&mut chunk, self.read_bytes as u64)?;
+            self.read_bytes += len;
+            return Ok(Some(chunk));
+        }
+        if self.memory.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(std::mem::take(&mut self.memory)))
+    }
+
+    /// Hand all cached output to `f` in order, in chunks.
+    pub fn replay<E: From<io::Error>>(
+        mut self,
+        mut f: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        while let Some(chunk) = self.next_chunk()? {
+            f(&chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Create the spill file under a random name in the user's runtime
+/// directory, or the temporary directory if there is none.
+fn create_spill_file() -> io::Result<File> {
+    let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    let mut last_error = None;
+    for _ in 0..SPILL_NAME_ATTEMPTS {
+        let path = dir.join(spill_file_name());
+        // Appending leaves reads at their own offsets
+        let result = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match result {
+            Ok(file) => {
+                let _ = fs::remove_file(&path);
+                return Ok(file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => last_error = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no spill file name available")))
+}
+
+/// A spill file name that other users can't guess. Each `RandomState` is
+/// seeded with fresh keys, which is random enough here.
+fn spill_file_name() -> PathBuf {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    PathBuf::from(format!("claude-chill-lookback-{:016x}", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replayed(cache: LookbackCache) -> Vec<u8> {
+        let mut output = Vec::new();
+        cache
+            .replay(|chunk| {
+                output.extend_from_slice(chunk);
+                Ok::<(), io::Error>(())
+            })
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_memory_until_full() {
+        let mut cache = LookbackCache::new(8);
+        cache.push(b"hello").unwrap();
+        assert!(!cache.is_full());
+        cache.push(b" world").unwrap();
+        assert!(cache.is_full());
+        assert_eq!(cache.take_memory(), b"hello world");
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_spill_round_trip() {
+        let mut cache = LookbackCache::new(4);
+        cache.push(b"before ").unwrap();
+        cache.spill().unwrap();
+        assert!(cache.is_spilling());
+        assert!(!cache.is_full());
+        let mut expected = b"before ".to_vec();
+        for i in 0..20_000 {
+            let line = format!("line {}\r\n", i);
+            cache.push(line.as_bytes()).unwrap();
+            expected.extend_from_slice(line.as_bytes());
+        }
+        assert_eq!(cache.len(), expected.len());
+        assert_eq!(replayed(cache), expected);
+    }
+
+    #[test]
+    fn test_spill_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut cache = LookbackCache::new(4);
+        cache.push(b"secret").unwrap();
+        cache.spill().unwrap();
+        let file = cache.spill.as_ref().unwrap();
+        assert_eq!(file.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        assert_ne!(spill_file_name(), spill_file_name());
+    }
+
+    #[test]
+    fn test_output_added_while_reading_chunks() {
+        let mut cache = LookbackCache::new(4);
+        cache.spill().unwrap();
+        let block = vec![b'x'; REPLAY_CHUNK_BYTES + 10];
+        cache.push(&block).unwrap();
+        assert_eq!(
+            cache.next_chunk().unwrap().unwrap().len(),
+            REPLAY_CHUNK_BYTES
+        );
+        cache.push(b"later").unwrap();
+        assert_eq!(cache.len(), 15);
+        let mut expected = vec![b'x'; 10];
+        expected.extend_from_slice(b"later");
+        assert_eq!(cache.next_chunk().unwrap().unwrap(), expected);
+        assert_eq!(cache.next_chunk().unwrap(), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_replay_without_spill() {
+        let mut cache = LookbackCache::new(1024);
+        cache.push(b"abc").unwrap();
+        assert_eq!(replayed(cache), b"abc");
+    }
+}
@@ -0,0 +1,191 @@
+//! Small LZ77 block compressor for cold history.
+//!
+//! The format follows the LZ4 block layout: each sequence is a token byte
+//! (literal length in the high nibble, match length minus 4 in the low
+//! nibble, 15 meaning "more length bytes follow"), the literals, then a
+//! little-endian u16 match offset. The last sequence has literals only.
+//! Terminal output is mostly repeated escape sequences and indentation, which
+//! this compresses well enough without a dependency.
+
+use anyhow::{Result, bail};
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 14;
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 16);
+    // Position + 1 of the last occurrence of each hashed 4-byte sequence
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let hash = hash(&input[pos..]);
+        let candidate = table[hash];
+        table[hash] = pos + 1;
+
+        if let Some(start) = candidate.checked_sub(1)
+            && pos - start <= MAX_OFFSET
+            && input[start..start + MIN_MATCH] == input[pos..pos + MIN_MATCH]
+        {
+            let mut length = MIN_MATCH;
+            while pos + length < input.len() && input[start + length] == input[pos + length] {
+                length += 1;
+            }
+            write_sequence(
+                &mut output,
+                &input[anchor..pos],
+                Some((pos - start, length)),
+            );
+            for p in pos + 1..(pos + length).min(input.len().saturating_sub(MIN_MATCH - 1)) {
+                table[hash_at(input, p)] = p + 1;
+            }
+            pos += length;
+            anchor = pos;
+        } else {
+            pos += 1;
+        }
+    }
+
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+pub fn decompress(input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    let mut pos = 0;
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+
+        let literals = read_length(input, &mut pos, usize::from(token >> 4))?;
+        let Some(bytes) = input.get(pos..pos + literals) else {
+            bail!("truncated literals");
+        };
+        output.extend_from_slice(bytes);
+        pos += literals;
+        if pos == input.len() {
+            break;
+        }
+
+        let Some(offset_bytes) = input.get(pos..pos + 2) else {
+            bail!("truncated offset");
+        };
+        let offset = usize::from(u16::from_le_bytes([offset_bytes[0], offset_bytes[1]]));
+        pos += 2;
+        let length = read_length(input, &mut pos, usize::from(token & 0x0f))? + MIN_MATCH;
+        if offset == 0 || offset > output.len() {
+            bail!("invalid match offset");
+        }
+        // Byte by byte, since a match may overlap the bytes it produces
+        let start = output.len() - offset;
+        for i in 0..length {
+            output.push(output[start + i]);
+        }
+    }
+    Ok(())
+}
+
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_length = matched.map_or(0, |(_, length)| length - MIN_MATCH);
+    let token = (literals.len().min(15) << 4) | match_length.min(15);
+    output.push(token as u8);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_length(output, match_length);
+    }
+}
+
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    if length < 15 {
+        return;
+    }
+    let mut rest = length - 15;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+fn read_length(input: &[u8], pos: &mut usize, nibble: usize) -> Result<usize> {
+    let mut length = nibble;
+    if nibble == 15 {
+        loop {
+            let Some(&byte) = input.get(*pos) else {
+                bail!("truncated length");
+            };
+            *pos += 1;
+            length += usize::from(byte);
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(length)
+}
+
+fn hash_at(input: &[u8], pos: usize) -> usize {
+    hash(&input[pos..])
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        let mut output = Vec::new();
+        decompress(&compressed, &mut output).unwrap();
+        assert_eq!(output, input);
+        compressed
+    }
+
+    #[test]
+    fn test_round_trip_small_inputs() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abc");
+        round_trip(b"abcd");
+        round_trip(b"aaaaaaaa");
+    }
+
+    #[test]
+    fn test_round_trip_terminal_output() {
+        let mut input = Vec::new();
+        for i in 0..2000 {
+            input.extend_from_slice(
+                format!(
+                    "\x1b[38;5;245m  {:>5} \x1b[0m\x1b[1mfn example_{}()\x1b[0m\r\n",
+                    i,
+                    i % 7
+                )
+                .as_bytes(),
+            );
+        }
+        let compressed = round_trip(&input);
+        assert!(compressed.len() * 4 < input.len());
+    }
+
+    #[test]
+    fn test_round_trip_long_literals_and_matches() {
+        // Incompressible run longer than 15 + 255 literals, then a long match
+        let mut input: Vec<u8> = (0..1000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        input.extend(std::iter::repeat_n(b'x', 5000));
+        round_trip(&input);
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_offset() {
+        let mut output = Vec::new();
+        assert!(decompress(&[0x10, b'a', 5, 0], &mut output).is_err());
+        assert!(decompress(&[0xf0], &mut output).is_err());
+    }
+}
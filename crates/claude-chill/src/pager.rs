@@ -0,0 +1,826 @@
+//! Interactive lookback pager.
+//!
+//! History is kept as compressed lines, and only the lines in view are
+//! replayed into a VT emulator the size of the viewport. That window is
+//! composed onto a second screen together with a status line. Frames are
+//! emitted as diffs against the previously displayed screen, the same way
+//! the proxy renders the live screen.
+
+use crate::line_buffer::LineBuffer;
+use crate::screen_history::write_cell_sgr;
+use regex::Regex;
+use termwiz::escape::Action;
+use termwiz::escape::parser::Parser;
+use termwiz::input::{InputEvent, InputParser, KeyCode, KeyEvent, Modifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagerCommand {
+    LineUp,
+    LineDown,
+    HalfPageUp,
+    HalfPageDown,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    SearchForward,
+    SearchBackward,
+    NextMatch,
+    PreviousMatch,
+    OlderGeneration,
+    NewerGeneration,
+    AllGenerations,
+    Export,
+    Quit,
+}
+
+/// Search prompt being edited after `/` or `?`.
+struct SearchPrompt {
+    query: String,
+    backward: bool,
+    origin_offset: usize,
+}
+
+/// Last confirmed (or in-progress) search.
+struct Search {
+    regex: Regex,
+    backward: bool,
+    current_row: Option<usize>,
+}
+
+pub struct Pager {
+    history: LineBuffer,
+    display: vt100::Parser,
+    prev_display: Option<vt100::Screen>,
+    input_parser: InputParser,
+    view_rows: u16,
+    cols: u16,
+    offset: usize,
+    max_offset: usize,
+    row_text: Option<Vec<String>>,
+    prompt: Option<SearchPrompt>,
+    search: Option<Search>,
+    message: Option<String>,
+    title: &'static str,
+}
+
+impl Pager {
+    /// Build a pager over `history` for a terminal of `rows` x `cols`.
+    /// The last row is reserved for the status line.
+    pub fn new(history: &[u8], rows: u16, cols: u16) -> Self {
+        let view_rows = rows.saturating_sub(1).max(1);
+        let mut lines = LineBuffer::new(usize::MAX);
+        lines.push_bytes(history);
+        lines.compact();
+        // The line after the last newline is shown even when empty, where
+        // the cursor would be
+        let total = lines.line_count() + usize::from(lines.partial_line().is_empty());
+        let max_offset = total.saturating_sub(usize::from(view_rows));
+
+        Self {
+            history: lines,
+            display: vt100::Parser::new(view_rows + 1, cols, 0),
+            prev_display: None,
+            input_parser: InputParser::new(),
+            view_rows,
+            cols,
+            offset: 0,
+            max_offset,
+            row_text: None,
+            prompt: None,
+            search: None,
+            message: None,
+            title: "LOOKBACK MODE",
+        }
+    }
+
+    /// Use `title` instead of "LOOKBACK MODE" in the status line.
+    pub fn with_title(mut self, title: &'static str) -> Self {
+        self.title = title;
+        self
+    }
+
+    /// Feed raw input to the pager. Returns the first command the pager
+    /// can't handle itself (quit, export or switching history generation);
+    /// later input is discarded. A lone ESC or unfinished escape sequence
+    /// is held back until more input arrives or `flush_input` is called.
+    pub fn handle_input(&mut self, data: &[u8]) -> Option<PagerCommand> {
+        self.parse_input(data, true)
+    }
+
+    /// Take input held back by `handle_input` as typed, once the rest of an
+    /// escape sequence has had time to arrive.
+    pub fn flush_input(&mut self) -> Option<PagerCommand> {
+        self.parse_input(&[], false)
+    }
+
+    fn parse_input(&mut self, data: &[u8], maybe_more: bool) -> Option<PagerCommand> {
+        let mut keys = Vec::new();
+        self.input_parser.parse(
+            data,
+            |event| {
+                if let InputEvent::Key(key) = event {
+                    keys.push(key);
+                }
+            },
+            maybe_more,
+        );
+
+        for key in keys {
+            if self.prompt.is_some() {
+                self.edit_prompt(&key);
+                continue;
+            }
+            match command_for_key(&key) {
+                Some(
+                    command @ (PagerCommand::OlderGeneration
+                    | PagerCommand::NewerGeneration
+                    | PagerCommand::AllGenerations
+                    | PagerCommand::Export
+                    | PagerCommand::Quit),
+                ) => return Some(command),
+                Some(command) => self.apply(command),
+                None => {}
+            }
+        }
+        None
+    }
+
+    /// Show a transient message in the status line until the next command.
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    pub fn apply(&mut self, command: PagerCommand) {
+        self.message = None;
+        let page = usize::from(self.view_rows);
+        let half_page = (page / 2).max(1);
+        let offset = match command {
+            PagerCommand::LineUp => self.offset.saturating_add(1),
+            PagerCommand::LineDown => self.offset.saturating_sub(1),
+            PagerCommand::HalfPageUp => self.offset.saturating_add(half_page),
+            PagerCommand::HalfPageDown => self.offset.saturating_sub(half_page),
+            PagerCommand::PageUp => self.offset.saturating_add(page),
+            PagerCommand::PageDown => self.offset.saturating_sub(page),
+            PagerCommand::Top => self.max_offset,
+            PagerCommand::Bottom => 0,
+            PagerCommand::SearchForward | PagerCommand::SearchBackward => {
+                self.prompt = Some(SearchPrompt {
+                    query: String::new(),
+                    backward: command == PagerCommand::SearchBackward,
+                    origin_offset: self.offset,
+                });
+                self.offset
+            }
+            PagerCommand::NextMatch | PagerCommand::PreviousMatch => {
+                self.repeat_search(command == PagerCommand::PreviousMatch);
+                self.offset
+            }
+            PagerCommand::OlderGeneration
+            | PagerCommand::NewerGeneration
+            | PagerCommand::AllGenerations
+            | PagerCommand::Export
+            | PagerCommand::Quit => self.offset,
+        };
+        self.offset = offset.min(self.max_offset);
+    }
+
+    /// Returns (first visible line, last visible line, total lines),
+    /// 1-based.
+    pub fn position(&self) -> (usize, usize, usize) {
+        let total = self.total_rows();
+        let first = self.top_row() + 1;
+        let last = first + usize::from(self.view_rows) - 1;
+        (first, last, total)
+    }
+
+    fn total_rows(&self) -> usize {
+        self.max_offset + usize::from(self.view_rows)
+    }
+
+    /// 0-based index of the line at the top of the viewport.
+    fn top_row(&self) -> usize {
+        self.max_offset - self.offset
+    }
+
+    fn scroll_to_row(&mut self, row: usize) {
+        self.offset = self.max_offset.saturating_sub(row);
+    }
+
+    fn edit_prompt(&mut self, key: &KeyEvent) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        match key.key {
+            KeyCode::Enter => {
+                let finished = self.prompt.take();
+                if finished.is_some_and(|p| p.query.is_empty()) {
+                    // Empty query repeats the previous search, like less
+                    self.repeat_search(false);
+                }
+            }
+            KeyCode::Escape => {
+                self.offset = prompt.origin_offset;
+                self.prompt = None;
+                self.search = None;
+                self.message = None;
+            }
+            KeyCode::Backspace => {
+                if prompt.query.pop().is_none() {
+                    self.offset = prompt.origin_offset;
+                    self.prompt = None;
+                } else {
+                    self.update_incremental_search();
+                }
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(Modifiers::CTRL) => {
+                prompt.query.push(c);
+                self.update_incremental_search();
+            }
+            _ => {}
+        }
+    }
+
+    /// Re-run the search being typed, starting from where the prompt opened.
+    fn update_incremental_search(&mut self) {
+        let Some(prompt) = self.prompt.as_ref() else {
+            return;
+        };
+        let backward = prompt.backward;
+        let origin_offset = prompt.origin_offset;
+        self.offset = origin_offset;
+        self.message = None;
+
+        if prompt.query.is_empty() {
+            self.search = None;
+            return;
+        }
+        let regex = match Regex::new(&prompt.query) {
+            Ok(regex) => regex,
+            Err(_) => {
+                self.search = None;
+                self.message = Some("invalid pattern".to_string());
+                return;
+            }
+        };
+
+        let start = self.top_row();
+        let found = self.find_row(&regex, start, backward, true);
+        self.search = Some(Search {
+            regex,
+            backward,
+            current_row: found,
+        });
+        match found {
+            Some(row) => self.jump_to_match(row),
+            None => self.message = Some("pattern not found".to_string()),
+        }
+    }
+
+    /// Jump to the next match of the last search. `reverse` searches
+    /// against the original direction (`N`).
+    fn repeat_search(&mut self, reverse: bool) {
+        let Some(search) = self.search.as_ref() else {
+            self.message = Some("no previous search".to_string());
+            return;
+        };
+        let backward = search.backward != reverse;
+        let start = search.current_row.unwrap_or_else(|| self.top_row());
+        let regex = search.regex.clone();
+        match self.find_row(&regex, start, backward, false) {
+            Some(row) => {
+                if let Some(search) = self.search.as_mut() {
+                    search.current_row = Some(row);
+                }
+                self.jump_to_match(row);
+            }
+            None => self.message = Some("pattern not found".to_string()),
+        }
+    }
+
+    fn jump_to_match(&mut self, row: usize) {
+        let top = self.top_row();
+        let visible = top..top + usize::from(self.view_rows);
+        if !visible.contains(&row) {
+            self.scroll_to_row(row);
+        }
+    }
+
+    /// Find the nearest row matching `regex` from `start`, wrapping around
+    /// the ends of history.
+    fn find_row(
+        &mut self,
+        regex: &Regex,
+        start: usize,
+        backward: bool,
+        inclusive: bool,
+    ) -> Option<usize> {
+        let rows = self.row_text();
+        let total = rows.len();
+        if total == 0 {
+            return None;
+        }
+        let start = start.min(total - 1);
+        let first_step = if inclusive { 0 } else { 1 };
+        let found = (first_step..total + first_step)
+            .map(|step| {
+                if backward {
+                    (start + total - step % total) % total
+                } else {
+                    (start + step) % total
+                }
+            })
+            .find(|&row| regex.is_match(&rows[row]));
+
+        if let Some(row) = found {
+            let wrapped = if backward { row > start } else { row < start };
+            if wrapped {
+                self.message = Some(if backward {
+                    "search hit TOP, continuing at BOTTOM".to_string()
+                } else {
+                    "search hit BOTTOM, continuing at TOP".to_string()
+                });
+            }
+        }
+        found
+    }
+
+    /// Plain text of every history line, built on first search.
+    fn row_text(&mut self) -> &Vec<String> {
+        if self.row_text.is_none() {
+            let mut history = Vec::new();
+            self.history.append_all(&mut history);
+            let mut rows: Vec<String> = history.split(|&b| b == b'\n').map(plain_text).collect();
+            rows.resize(self.total_rows(), String::new());
+            self.row_text = Some(rows);
+        }
+        self.row_text.get_or_insert_with(Vec::new)
+    }
+
+    /// Replay the lines in view into a screen the size of the viewport.
+    fn window(&self) -> vt100::Parser {
+        let top = self.top_row();
+        let rows = usize::from(self.view_rows);
+        let mut bytes = Vec::new();
+        self.history.append_lines(top, rows, &mut bytes);
+        if bytes.last() == Some(&b'\n') {
+            bytes.pop();
+        }
+        let mut window = vt100::Parser::new(self.view_rows, self.cols, rows);
+        window.process(&bytes);
+        // Lines that wrap push the last ones off the bottom. Show the window
+        // from its first line, except at the end of history, where the last
+        // line stays in view the way it would on the terminal
+        if top + rows < self.total_rows() {
+            window.screen_mut().set_scrollback(usize::MAX);
+        }
+        window
+    }
+
+    /// Append the escape sequences needed to show the current viewport.
+    /// The first frame is a full redraw, later frames are diffs.
+    pub fn render(&mut self, hint: &str, output: &mut Vec<u8>) {
+        self.compose(hint);
+        let screen = self.display.screen();
+        match &self.prev_display {
+            Some(prev) => output.extend_from_slice(&screen.contents_diff(prev)),
+            None => output.extend_from_slice(&screen.contents_formatted()),
+        }
+        self.prev_display = Some(screen.clone());
+    }
+
+    /// Append the escape sequences that draw the pager over the top rows of
+    /// a terminal showing something else, leaving the rows below it alone.
+    /// Every row is redrawn, since whatever is underneath may have been
+    /// drawn over it since the last frame.
+    pub fn render_overlay(&mut self, hint: &str, output: &mut Vec<u8>) {
+        self.compose(hint);
+        let screen = self.display.screen();
+        for (i, row) in screen.rows_formatted(0, self.cols).enumerate() {
+            output.extend_from_slice(format!("\x1b[{};1H\x1b[m\x1b[2K", i + 1).as_bytes());
+            output.extend_from_slice(&row);
+        }
+        output.extend_from_slice(b"\x1b[m");
+        self.prev_display = None;
+    }
+
+    /// Draw the viewport and status line onto the display screen.
+    fn compose(&mut self, hint: &str) {
+        let window = self.window();
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(b"\x1b[?25l\x1b[m\x1b[H\x1b[2J");
+        for (i, row) in window.screen().rows_formatted(0, self.cols).enumerate() {
+            frame.extend_from_slice(format!("\x1b[{};1H", i + 1).as_bytes());
+            frame.extend_from_slice(&row);
+            frame.extend_from_slice(b"\x1b[m");
+        }
+        self.write_highlights(window.screen(), &mut frame);
+        frame.extend_from_slice(format!("\x1b[{};1H\x1b[7m", self.view_rows + 1).as_bytes());
+        frame.extend_from_slice(self.status_line(hint).as_bytes());
+        frame.extend_from_slice(b"\x1b[m");
+        self.display.process(&frame);
+    }
+
+    /// Redraw matched cells in the viewport with their own styling inverted.
+    fn write_highlights(&self, screen: &vt100::Screen, frame: &mut Vec<u8>) {
+        let Some(search) = self.search.as_ref() else {
+            return;
+        };
+        for row in 0..self.view_rows {
+            let text = row_text(screen, row, self.cols);
+            for m in search.regex.find_iter(&text.text) {
+                if m.is_empty() {
+                    continue;
+                }
+                let start_col = text.columns[m.start()];
+                let end_col = text.columns[m.end()];
+                frame.extend_from_slice(format!("\x1b[{};{}H", row + 1, start_col + 1).as_bytes());
+                for col in start_col..end_col {
+                    let Some(cell) = screen.cell(row, col) else {
+                        continue;
+                    };
+                    if cell.is_wide_continuation() {
+                        continue;
+                    }
+                    write_cell_sgr(cell, !cell.inverse(), frame);
+                    if cell.has_contents() {
+                        frame.extend_from_slice(cell.contents().as_bytes());
+                    } else {
+                        frame.push(b' ');
+                    }
+                }
+                frame.extend_from_slice(b"\x1b[m");
+            }
+        }
+    }
+
+    fn status_line(&self, hint: &str) -> String {
+        let line = match &self.prompt {
+            Some(prompt) => {
+                let marker = if prompt.backward { '?' } else { '/' };
+                match &self.message {
+                    Some(message) => format!("{}{}  ({})", marker, prompt.query, message),
+                    None => format!("{}{}", marker, prompt.query),
+                }
+            }
+            None => {
+                let (first, last, total) = self.position();
+                let percent = ((self.max_offset - self.offset) * 100)
+                    .checked_div(self.max_offset)
+                    .unwrap_or(100);
+                let mut line = format!(
+                    " {}  {}-{}/{} ({}%)  j/k PgUp/PgDn ^U/^D g/G / ? n/N [ ] a s  {} ",
+                    self.title, first, last, total, percent, hint
+                );
+                if let Some(message) = &self.message {
+                    line = format!(" {} |{}", message, line);
+                }
+                line
+            }
+        };
+        let width = usize::from(self.cols);
+        let mut line: String = line.chars().take(width).collect();
+        let len = line.chars().count();
+        line.extend(std::iter::repeat_n(' ', width - len));
+        line
+    }
+}
+
+/// Plain text of one screen row, with the column of every byte.
+struct RowText {
+    text: String,
+    /// `columns[i]` is the screen column of the character containing byte
+    /// `i`; the extra final entry is the column just past the text.
+    columns: Vec<u16>,
+}
+
+fn row_text(screen: &vt100::Screen, row: u16, cols: u16) -> RowText {
+    let mut text = String::new();
+    let mut columns = Vec::new();
+    let mut col = 0;
+    let mut end_col = 0;
+    while col < cols {
+        let Some(cell) = screen.cell(row, col) else {
+            break;
+        };
+        let contents = if cell.has_contents() {
+            cell.contents()
+        } else {
+            " "
+        };
+        text.push_str(contents);
+        columns.resize(text.len(), col);
+        col += if cell.is_wide() { 2 } else { 1 };
+        if contents.chars().any(|c| c != ' ') {
+            end_col = col;
+        }
+    }
+    let trimmed = text.trim_end_matches(' ').len();
+    text.truncate(trimmed);
+    columns.truncate(trimmed);
+    columns.push(end_col);
+    RowText { text, columns }
+}
+
+/// Text of one history line with escape sequences and controls removed.
+fn plain_text(line: &[u8]) -> String {
+    let mut text = String::new();
+    Parser::new().parse(line, |action| match action {
+        Action::Print(c) => text.push(c),
+        Action::PrintString(s) => text.push_str(&s),
+        _ => {}
+    });
+    text
+}
+
+fn command_for_key(key: &KeyEvent) -> Option<PagerCommand> {
+    let ctrl = key.modifiers.contains(Modifiers::CTRL);
+    let command = match key.key {
+        KeyCode::Char('b') if ctrl => PagerCommand::PageUp,
+        KeyCode::Char('f') if ctrl => PagerCommand::PageDown,
+        KeyCode::Char('u') if ctrl => PagerCommand::HalfPageUp,
+        KeyCode::Char('d') if ctrl => PagerCommand::HalfPageDown,
+        _ if ctrl => return None,
+        KeyCode::Char('k') | KeyCode::UpArrow | KeyCode::ApplicationUpArrow => PagerCommand::LineUp,
+        KeyCode::Char('j')
+        | KeyCode::DownArrow
+        | KeyCode::ApplicationDownArrow
+        | KeyCode::Enter => PagerCommand::LineDown,
+        KeyCode::Char('u') => PagerCommand::HalfPageUp,
+        KeyCode::Char('d') => PagerCommand::HalfPageDown,
+        KeyCode::Char('b') | KeyCode::PageUp => PagerCommand::PageUp,
+        KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => PagerCommand::PageDown,
+        KeyCode::Char('g') | KeyCode::Home => PagerCommand::Top,
+        KeyCode::Char('G') | KeyCode::End => PagerCommand::Bottom,
+        KeyCode::Char('/') => PagerCommand::SearchForward,
+        KeyCode::Char('?') => PagerCommand::SearchBackward,
+        KeyCode::Char('n') => PagerCommand::NextMatch,
+        KeyCode::Char('N') => PagerCommand::PreviousMatch,
+        KeyCode::Char('[') => PagerCommand::OlderGeneration,
+        KeyCode::Char(']') => PagerCommand::NewerGeneration,
+        KeyCode::Char('a') => PagerCommand::AllGenerations,
+        KeyCode::Char('s') => PagerCommand::Export,
+        KeyCode::Char('q') => PagerCommand::Quit,
+        _ => return None,
+    };
+    Some(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_lines(n: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for i in 1..=n {
+            out.extend_from_slice(format!("line{}\r\n", i).as_bytes());
+        }
+        out
+    }
+
+    fn visible_text(pager: &mut Pager) -> String {
+        let mut output = Vec::new();
+        pager.render("", &mut output);
+        pager.display.screen().contents()
+    }
+
+    #[test]
+    fn test_starts_at_bottom() {
+        let mut pager = Pager::new(&numbered_lines(50), 11, 40);
+        assert_eq!(pager.position(), (42, 51, 51));
+        let text = visible_text(&mut pager);
+        assert!(text.contains("line50"));
+        assert!(!text.contains("line40\n"));
+    }
+
+    #[test]
+    fn test_top_and_bottom() {
+        let mut pager = Pager::new(&numbered_lines(50), 11, 40);
+        pager.apply(PagerCommand::Top);
+        assert_eq!(pager.position().0, 1);
+        assert!(visible_text(&mut pager).starts_with("line1\n"));
+        pager.apply(PagerCommand::Bottom);
+        assert_eq!(pager.position().0, 42);
+    }
+
+    #[test]
+    fn test_scrolling_is_clamped() {
+        let mut pager = Pager::new(&numbered_lines(5), 11, 40);
+        pager.apply(PagerCommand::PageUp);
+        assert_eq!(pager.position().0, 1);
+        pager.apply(PagerCommand::LineDown);
+        assert_eq!(pager.position().0, 1);
+    }
+
+    #[test]
+    fn test_page_and_half_page() {
+        let mut pager = Pager::new(&numbered_lines(100), 11, 40);
+        pager.apply(PagerCommand::PageUp);
+        assert_eq!(pager.position().0, 82);
+        pager.apply(PagerCommand::HalfPageDown);
+        assert_eq!(pager.position().0, 87);
+        pager.apply(PagerCommand::LineUp);
+        assert_eq!(pager.position().0, 86);
+    }
+
+    fn decode(data: &[u8]) -> Vec<PagerCommand> {
+        InputParser::new()
+            .parse_as_vec(data, false)
+            .into_iter()
+            .filter_map(|event| match event {
+                InputEvent::Key(key) => command_for_key(&key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_keys() {
+        assert_eq!(
+            decode(b"jkgG\x1b[5~\x1b[6~\x15\x04/?nN[]asq"),
+            vec![
+                PagerCommand::LineDown,
+                PagerCommand::LineUp,
+                PagerCommand::Top,
+                PagerCommand::Bottom,
+                PagerCommand::PageUp,
+                PagerCommand::PageDown,
+                PagerCommand::HalfPageUp,
+                PagerCommand::HalfPageDown,
+                PagerCommand::SearchForward,
+                PagerCommand::SearchBackward,
+                PagerCommand::NextMatch,
+                PagerCommand::PreviousMatch,
+                PagerCommand::OlderGeneration,
+                PagerCommand::NewerGeneration,
+                PagerCommand::AllGenerations,
+                PagerCommand::Export,
+                PagerCommand::Quit,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_ignores_unbound_keys() {
+        assert!(decode(b"xyz\x01").is_empty());
+    }
+
+    #[test]
+    fn test_handle_input_quit() {
+        let mut pager = Pager::new(&numbered_lines(50), 11, 40);
+        assert_eq!(pager.handle_input(b"g"), None);
+        assert_eq!(pager.position().0, 1);
+        assert_eq!(pager.handle_input(b"q"), Some(PagerCommand::Quit));
+        assert_eq!(pager.handle_input(b"sj"), Some(PagerCommand::Export));
+    }
+
+    #[test]
+    fn test_search_forward_and_next() {
+        let mut pager = Pager::new(&numbered_lines(100), 11, 40);
+        pager.handle_input(b"g/line7\r");
+        // line7 is visible from the top, so the viewport stays put
+        assert_eq!(pager.position().0, 1);
+        pager.handle_input(b"n");
+        assert_eq!(pager.position().0, 70);
+        // line71 is already on screen, so only the current match moves
+        pager.handle_input(b"n");
+        assert_eq!(pager.position().0, 70);
+        assert_eq!(pager.search.as_ref().unwrap().current_row, Some(70));
+        pager.handle_input(b"N");
+        assert_eq!(pager.search.as_ref().unwrap().current_row, Some(69));
+    }
+
+    #[test]
+    fn test_search_is_incremental() {
+        let mut pager = Pager::new(&numbered_lines(100), 11, 40);
+        pager.handle_input(b"g/line4");
+        assert_eq!(pager.position().0, 1);
+        pager.handle_input(b"2");
+        assert_eq!(pager.position().0, 42);
+        // Escape cancels and restores the original position, once it's
+        // clear it doesn't start a longer sequence
+        pager.handle_input(b"\x1b");
+        assert_eq!(pager.position().0, 42);
+        assert_eq!(pager.flush_input(), None);
+        assert_eq!(pager.position().0, 1);
+        assert!(pager.search.is_none());
+    }
+
+    #[test]
+    fn test_escape_sequence_split_across_reads() {
+        let mut pager = Pager::new(&numbered_lines(100), 11, 40);
+        assert_eq!(pager.handle_input(b"\x1b["), None);
+        assert_eq!(pager.position().0, 92);
+        pager.handle_input(b"5~");
+        assert_eq!(pager.position().0, 82);
+    }
+
+    #[test]
+    fn test_window_rendered_from_lines() {
+        let mut history = numbered_lines(20);
+        history.extend_from_slice(format!("{}\r\nlast\r\n", "x".repeat(100)).as_bytes());
+        let mut pager = Pager::new(&history, 6, 40);
+        assert_eq!(pager.position(), (19, 23, 23));
+
+        // At the bottom the wrapped line pushes earlier ones off the top
+        let text = visible_text(&mut pager);
+        assert!(text.starts_with("line20\nxxxx"), "{:?}", text);
+        assert!(text.contains("\nlast\n"));
+
+        // Further up the window starts at its first line
+        pager.apply(PagerCommand::LineUp);
+        assert!(visible_text(&mut pager).starts_with("line18\nline19\nline20\n"));
+    }
+
+    #[test]
+    fn test_search_backward_wraps() {
+        let mut pager = Pager::new(&numbered_lines(100), 11, 40);
+        pager.handle_input(b"g?line99\r");
+        assert_eq!(pager.position().0, 92);
+        assert!(pager.message.as_deref().unwrap().contains("hit TOP"));
+    }
+
+    #[test]
+    fn test_search_regex_and_not_found() {
+        let mut pager = Pager::new(&numbered_lines(100), 11, 40);
+        pager.handle_input(b"g/^line5[0-9]$\r");
+        assert_eq!(pager.position().0, 50);
+        pager.handle_input(b"/nomatch\r");
+        assert_eq!(pager.message.as_deref(), Some("pattern not found"));
+        assert_eq!(pager.position().0, 50);
+    }
+
+    #[test]
+    fn test_search_runs_over_stripped_text() {
+        let history = b"plain\r\n\x1b[31mred\x1b[0m \x1b[1mbold\x1b[0m\r\n";
+        let mut pager = Pager::new(history, 11, 40);
+        pager.handle_input(b"/red bold\r");
+        assert_eq!(pager.search.as_ref().unwrap().current_row, Some(1));
+    }
+
+    #[test]
+    fn test_highlight_keeps_original_colors() {
+        let history = b"\x1b[31mred text\x1b[0m\r\n";
+        let mut pager = Pager::new(history, 11, 40);
+        pager.handle_input(b"/text\r");
+        let mut output = Vec::new();
+        pager.render("", &mut output);
+        let screen = pager.display.screen();
+        let highlighted = screen.cell(0, 4).unwrap();
+        assert!(highlighted.inverse());
+        assert_eq!(highlighted.fgcolor(), vt100::Color::Idx(1));
+        assert!(!screen.cell(0, 0).unwrap().inverse());
+    }
+
+    #[test]
+    fn test_row_text_columns_with_wide_chars() {
+        let mut parser = vt100::Parser::new(2, 20, 0);
+        parser.process("a\u{4e2d}b".as_bytes());
+        let text = row_text(parser.screen(), 0, 20);
+        assert_eq!(text.text, "a\u{4e2d}b");
+        assert_eq!(text.columns[0], 0);
+        assert_eq!(text.columns[1], 1);
+        assert_eq!(text.columns[4], 3);
+        assert_eq!(text.columns[5], 4);
+    }
+
+    #[test]
+    fn test_status_line_shows_position() {
+        let mut pager = Pager::new(&numbered_lines(50), 11, 80);
+        pager.apply(PagerCommand::Top);
+        let text = visible_text(&mut pager);
+        assert!(text.contains("LOOKBACK MODE"));
+        assert!(text.contains("1-10/51 (0%)"));
+    }
+
+    #[test]
+    fn test_second_render_is_diff() {
+        let mut pager = Pager::new(&numbered_lines(50), 11, 40);
+        let mut first = Vec::new();
+        pager.render("", &mut first);
+        let mut second = Vec::new();
+        pager.render("", &mut second);
+        assert!(second.len() < first.len());
+    }
+
+    #[test]
+    fn test_overlay_leaves_rows_below() {
+        let mut terminal = vt100::Parser::new(12, 40, 0);
+        for i in 0..12 {
+            terminal.process(format!("\x1b[{};1Hlive row {}", i + 1, i).as_bytes());
+        }
+        let mut pager = Pager::new(&numbered_lines(50), 6, 40).with_title("PEEK");
+        let mut output = Vec::new();
+        pager.render_overlay("", &mut output);
+        terminal.process(&output);
+
+        let screen = terminal.screen();
+        let row = |r: u16| screen.contents_between(r, 0, r, 40);
+        assert_eq!(row(0), "line47");
+        assert_eq!(row(3), "line50");
+        assert!(row(5).starts_with(" PEEK  "));
+        assert_eq!(row(6), "live row 6");
+        assert_eq!(row(11), "live row 11");
+    }
+}
@@ -1,10 +1,26 @@
+use crate::config::{ForwardPolicy, HistoryMode, LookbackInput, LookbackMode};
 use crate::escape_sequences::{
     ALT_SCREEN_ENTER, ALT_SCREEN_ENTER_LEGACY, ALT_SCREEN_EXIT, ALT_SCREEN_EXIT_LEGACY,
-    CLEAR_SCREEN, CURSOR_HOME, INPUT_BUFFER_CAPACITY, OUTPUT_BUFFER_CAPACITY, SYNC_BUFFER_CAPACITY,
-    SYNC_END, SYNC_START,
+    CLEAR_SCREEN, CURSOR_HOME, OUTPUT_BUFFER_CAPACITY, SYNC_BUFFER_CAPACITY, SYNC_END, SYNC_START,
 };
+use crate::export;
+use crate::frame_dedup::FrameDedup;
 use crate::history_filter::HistoryFilter;
+use crate::history_generations::{HistoryGenerations, HistoryView};
+use crate::hyperlinks::{Hyperlinks, Piece};
+use crate::input_decoder::{InputDecoder, KeyInput, partial_marker_len};
+use crate::input_queue::InputQueue;
+use crate::key_parser::{KeyCode, KeyCombination, Modifiers};
+use crate::keyboard_modes::KeyboardModes;
+use crate::keymap::{KeyAction, KeyMatch, Keymap};
 use crate::line_buffer::LineBuffer;
+use crate::linkify::Linkifier;
+use crate::lookback_cache::LookbackCache;
+use crate::pager::{Pager, PagerCommand};
+use crate::redraw_throttler::RedrawThrottler;
+use crate::screen_history;
+use crate::sessions::{self, SessionMeta, SessionStore};
+use crate::side_channel::SideChannelFilter;
 use anyhow::{Context, Result};
 use log::debug;
 use memchr::memmem;
@@ -15,26 +31,19 @@ use nix::pty::{Winsize, openpty};
 use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, kill, sigaction};
 use nix::sys::termios::{SetArg, Termios, cfmakeraw, tcgetattr, tcsetattr};
 use nix::unistd::{Pid, isatty, read, write};
+use std::fs;
 use std::io;
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
 use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, ExitStatus};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
-use termwiz::escape::Action;
-use termwiz::escape::csi::{CSI, Keyboard};
-use termwiz::escape::parser::Parser as TermwizParser;
 
 static SIGWINCH_RECEIVED: AtomicBool = AtomicBool::new(false);
 static SIGINT_RECEIVED: AtomicBool = AtomicBool::new(false);
 static SIGTERM_RECEIVED: AtomicBool = AtomicBool::new(false);
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum SequenceMatch {
-    Complete,
-    Partial,
-    None,
-}
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
 
 extern "C" fn handle_sigwinch(_: libc::c_int) {
     SIGWINCH_RECEIVED.store(true, Ordering::SeqCst);
@@ -48,22 +57,61 @@ extern "C" fn handle_sigterm(_: libc::c_int) {
     SIGTERM_RECEIVED.store(true, Ordering::SeqCst);
 }
 
+extern "C" fn handle_sighup(_: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+}
+
 pub struct ProxyConfig {
     pub max_history_lines: usize,
-    pub lookback_key: String,
-    pub lookback_sequence_legacy: Vec<u8>,
-    pub lookback_sequence_kitty: Vec<u8>,
+    pub max_history_bytes: usize,
+    pub max_history_generations: usize,
+    pub keymap: Keymap,
     pub auto_lookback_timeout_ms: u64,
+    /// How long a confirm key waits for its second press
+    pub confirm_timeout_ms: u64,
+    pub redraw_throttle_ms: u64,
+    pub lookback_mode: LookbackMode,
+    pub lookback_input: LookbackInput,
+    pub lookback_cache_bytes: usize,
+    pub history_mode: HistoryMode,
+    pub dedupe_frames: bool,
+    pub linkify: bool,
+    /// Side channels forwarded from main-screen output
+    pub forward: ForwardPolicy,
+    pub export_path: Option<PathBuf>,
+    pub export_on_exit: Option<PathBuf>,
+    pub session_dir: Option<PathBuf>,
+    pub max_sessions: usize,
 }
 
 impl Default for ProxyConfig {
     fn default() -> Self {
+        let mut keymap = Keymap::new();
+        for (key, action) in [
+            ("[ctrl][6]", KeyAction::ToggleLookback),
+            ("[ctrl][c]", KeyAction::ExitLookback),
+        ] {
+            keymap.bind(key, action).expect("default keys are valid");
+        }
         Self {
             max_history_lines: 100_000,
-            lookback_key: "[ctrl][6]".to_string(),
-            lookback_sequence_legacy: vec![0x1E],
-            lookback_sequence_kitty: b"\x1b[54;5u".to_vec(),
+            max_history_bytes: 64 * 1024 * 1024,
+            max_history_generations: 5,
+            keymap,
             auto_lookback_timeout_ms: 15000,
+            confirm_timeout_ms: 2000,
+            redraw_throttle_ms: 50,
+            lookback_mode: LookbackMode::Dump,
+            lookback_input: LookbackInput::Discard,
+            lookback_cache_bytes: 8 * 1024 * 1024,
+            history_mode: HistoryMode::Raw,
+            dedupe_frames: false,
+            linkify: false,
+            forward: ForwardPolicy::default(),
+            export_path: None,
+            export_on_exit: None,
+            session_dir: None,
+            max_sessions: 100,
         }
     }
 }
@@ -94,15 +142,30 @@ impl Drop for TerminalGuard {
 const RENDER_DELAY_MS: u64 = 5;
 const SYNC_BLOCK_DELAY_MS: u64 = 50;
 
+/// Peek mode shows history over this fraction of the terminal's rows.
+const PEEK_ROWS_DIVISOR: u16 = 2;
+
+/// How long a notice stays on the bottom row.
+const NOTICE_MS: u64 = 2000;
+
 pub struct Proxy {
     config: ProxyConfig,
     pty_master: OwnedFd,
+    /// Input the pty hasn't taken yet
+    pty_input: InputQueue,
     child: Child,
     original_termios: Option<Termios>,
     history: LineBuffer,
+    history_generations: HistoryGenerations,
+    history_view: HistoryView,
+    frame_dedup: FrameDedup,
     history_filter: HistoryFilter,
+    side_channel_filter: SideChannelFilter,
+    hyperlinks: Hyperlinks,
+    linkifier: Option<Linkifier>,
     vt_parser: vt100::Parser,
     vt_prev_screen: Option<vt100::Screen>,
+    redraw_throttler: RedrawThrottler,
     last_output_time: Option<Instant>,
     last_render_time: Option<Instant>,
     last_stdin_time: Option<Instant>,
@@ -111,13 +174,36 @@ pub struct Proxy {
     sync_buffer: Vec<u8>,
     in_sync_block: bool,
     in_lookback_mode: bool,
+    in_peek_mode: bool,
     in_alternate_screen: bool,
-    kitty_mode_supported: bool,
-    kitty_mode_stack: u32,
-    kitty_output_parser: TermwizParser,
+    /// Output goes to the terminal as is instead of through the VT emulator
+    passthrough: bool,
+    /// Passthrough is off while lookback or peek is open, and comes back on
+    /// when it closes
+    passthrough_suspended: bool,
+    /// Message drawn over the bottom row until it expires
+    notice: Option<(String, Instant)>,
+    /// Confirm key pressed once, and when the chance to press it again ends
+    confirm_pending: Option<(KeyCombination, Instant)>,
+    /// Kitty keyboard protocol stack the child built on the main screen
+    keyboard_modes: KeyboardModes,
+    /// The child's keyboard modes are popped while lookback is open
+    keyboard_modes_suspended: bool,
     vt_render_pending: bool,
-    lookback_cache: Vec<u8>,
-    lookback_input_buffer: Vec<u8>,
+    lookback_cache: LookbackCache,
+    /// Output held back for lookback is still being written out after it
+    /// exited, and new output queues behind it
+    replaying_lookback_cache: bool,
+    /// Keys typed during lookback, sent to the child when it exits
+    queued_input: Vec<u8>,
+    /// Side channels in output caught up on during lookback, written to
+    /// the terminal when it exits
+    caught_up_side_channels: Vec<u8>,
+    pager: Option<Pager>,
+    session: Option<SessionMeta>,
+    input_decoder: InputDecoder,
+    /// Chord prefix key that was pressed, while waiting for the next key
+    chord_prefix: Option<KeyInput>,
     output_buffer: Vec<u8>,
     sync_start_finder: memmem::Finder<'static>,
     sync_end_finder: memmem::Finder<'static>,
@@ -129,88 +215,6 @@ pub struct Proxy {
     alt_screen_exit_legacy_finder: memmem::Finder<'static>,
 }
 
-/// Returns (supported, initial_flags) - if flags > 0, terminal is already in Kitty mode
-fn detect_kitty_support() -> (bool, u32) {
-    use std::io::Write;
-    use termwiz::escape::csi::Device;
-
-    // Query sequences:
-    // CSI ? u       - Kitty keyboard protocol query
-    // CSI c         - Primary Device Attributes (all terminals respond)
-    const KITTY_QUERY: &[u8] = b"\x1b[?u";
-    const DA_QUERY: &[u8] = b"\x1b[c";
-
-    // Send both queries
-    let stdout = std::io::stdout();
-    let mut stdout_lock = stdout.lock();
-    if stdout_lock.write_all(KITTY_QUERY).is_err() {
-        return (false, 0);
-    }
-    if stdout_lock.write_all(DA_QUERY).is_err() {
-        return (false, 0);
-    }
-    if stdout_lock.flush().is_err() {
-        return (false, 0);
-    }
-    drop(stdout_lock);
-
-    // Read responses with timeout using termwiz parser
-    let stdin = std::io::stdin();
-    let mut parser = TermwizParser::new();
-    let mut buf = [0u8; 256];
-    let mut kitty_supported = false;
-    let mut kitty_flags: u32 = 0;
-    let start = std::time::Instant::now();
-    let timeout = std::time::Duration::from_millis(500);
-    let poll_interval = PollTimeout::from(50u16);
-
-    while start.elapsed() < timeout {
-        let mut poll_fd = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
-
-        match poll(&mut poll_fd, poll_interval) {
-            Ok(0) => continue,
-            Ok(_) => {
-                match read(stdin.as_fd(), &mut buf) {
-                    Ok(0) => continue,
-                    Ok(n) => {
-                        let actions = parser.parse_as_vec(&buf[..n]);
-                        for action in actions {
-                            if let Action::CSI(csi) = action {
-                                match csi {
-                                    CSI::Keyboard(Keyboard::ReportKittyState(flags)) => {
-                                        kitty_supported = true;
-                                        kitty_flags = u32::from(flags.bits());
-                                    }
-                                    CSI::Device(dev)
-                                        if matches!(*dev, Device::DeviceAttributes(_)) =>
-                                    {
-                                        // DA response means all responses received
-                                        debug!(
-                                            "Kitty detection complete: supported={} flags={}",
-                                            kitty_supported, kitty_flags
-                                        );
-                                        return (kitty_supported, kitty_flags);
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
-                    Err(e) if e == Errno::EAGAIN || e == Errno::EWOULDBLOCK => continue,
-                    Err(_) => break,
-                }
-            }
-            Err(_) => continue,
-        }
-    }
-
-    debug!(
-        "Kitty detection timed out, supported={} flags={}",
-        kitty_supported, kitty_flags
-    );
-    (kitty_supported, kitty_flags)
-}
-
 impl Proxy {
     pub fn spawn(command: &str, args: &[&str], config: ProxyConfig) -> Result<Self> {
         let winsize = get_terminal_size()?;
@@ -219,11 +223,6 @@ impl Proxy {
         let terminal_guard = TerminalGuard::new()?;
         setup_signal_handlers()?;
 
-        // Detect Kitty support before spawning child
-        // If flags > 0, terminal is already in Kitty mode (inherited from parent)
-        let (kitty_supported, kitty_initial_flags) = detect_kitty_support();
-        let kitty_initial_stack = if kitty_initial_flags > 0 { 1 } else { 0 };
-
         let slave_fd = pty.slave.as_raw_fd();
 
         let child = unsafe {
@@ -257,26 +256,71 @@ impl Proxy {
         drop(pty.slave);
         set_nonblocking(&pty.master)?;
 
-        let vt_parser = vt100::Parser::new(winsize.ws_row, winsize.ws_col, 0);
+        debug!("Proxy::spawn: command={} args={:?}", command, args);
+
+        let session = config.session_dir.as_ref().map(|_| {
+            let command_line = std::iter::once(command)
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let cwd = std::env::current_dir()
+                .map(|d| d.display().to_string())
+                .unwrap_or_default();
+            SessionMeta::start(command_line, cwd)
+        });
+
+        Ok(Self::new(
+            config,
+            pty.master,
+            child,
+            winsize,
+            terminal_guard.take(),
+            session,
+        ))
+    }
+
+    fn new(
+        config: ProxyConfig,
+        pty_master: OwnedFd,
+        child: Child,
+        winsize: Winsize,
+        original_termios: Option<Termios>,
+        session: Option<SessionMeta>,
+    ) -> Self {
+        let scrollback_len = match config.history_mode {
+            HistoryMode::Raw => 0,
+            HistoryMode::Screen => screen_history::SCROLLBACK_ROWS,
+        };
+        let vt_parser = vt100::Parser::new(winsize.ws_row, winsize.ws_col, scrollback_len);
 
         // Seed history with clear screen so replay starts fresh
-        let mut history = LineBuffer::new(config.max_history_lines);
+        let mut history =
+            LineBuffer::new(config.max_history_lines).with_max_bytes(config.max_history_bytes);
         history.push_bytes(CLEAR_SCREEN);
         history.push_bytes(CURSOR_HOME);
 
         let auto_lookback_timeout = Duration::from_millis(config.auto_lookback_timeout_ms);
+        let redraw_throttler = RedrawThrottler::new(config.redraw_throttle_ms);
 
-        debug!("Proxy::spawn: command={} args={:?}", command, args);
-
-        Ok(Self {
+        Self {
             history,
+            history_generations: HistoryGenerations::new(config.max_history_generations),
+            history_view: HistoryView::default(),
+            frame_dedup: FrameDedup::new(),
+            lookback_cache: LookbackCache::new(config.lookback_cache_bytes),
+            replaying_lookback_cache: false,
             history_filter: HistoryFilter::new(),
+            side_channel_filter: SideChannelFilter::new(config.forward),
+            hyperlinks: Hyperlinks::new(),
+            linkifier: config.linkify.then(Linkifier::new),
             config,
-            pty_master: pty.master,
+            pty_master,
+            pty_input: InputQueue::new(),
             child,
-            original_termios: terminal_guard.take(),
+            original_termios,
             vt_parser,
             vt_prev_screen: None,
+            redraw_throttler,
             last_output_time: None,
             last_render_time: None,
             last_stdin_time: None,
@@ -285,13 +329,21 @@ impl Proxy {
             sync_buffer: Vec::with_capacity(SYNC_BUFFER_CAPACITY),
             in_sync_block: false,
             in_lookback_mode: false,
+            in_peek_mode: false,
             in_alternate_screen: false,
-            kitty_mode_supported: kitty_supported,
-            kitty_mode_stack: kitty_initial_stack,
-            kitty_output_parser: TermwizParser::new(),
+            passthrough: false,
+            passthrough_suspended: false,
+            notice: None,
+            confirm_pending: None,
+            keyboard_modes: KeyboardModes::new(),
+            keyboard_modes_suspended: false,
             vt_render_pending: false,
-            lookback_cache: Vec::new(),
-            lookback_input_buffer: Vec::with_capacity(INPUT_BUFFER_CAPACITY),
+            queued_input: Vec::new(),
+            caught_up_side_channels: Vec::new(),
+            pager: None,
+            session,
+            input_decoder: InputDecoder::new(),
+            chord_prefix: None,
             output_buffer: Vec::with_capacity(OUTPUT_BUFFER_CAPACITY),
             sync_start_finder: memmem::Finder::new(SYNC_START),
             sync_end_finder: memmem::Finder::new(SYNC_END),
@@ -301,7 +353,7 @@ impl Proxy {
             alt_screen_exit_finder: memmem::Finder::new(ALT_SCREEN_EXIT),
             alt_screen_enter_legacy_finder: memmem::Finder::new(ALT_SCREEN_ENTER_LEGACY),
             alt_screen_exit_legacy_finder: memmem::Finder::new(ALT_SCREEN_EXIT_LEGACY),
-        })
+        }
     }
 
     pub fn run(&mut self) -> Result<i32> {
@@ -309,34 +361,50 @@ impl Proxy {
         let stdout_fd = io::stdout();
 
         let mut buf = [0u8; 65536];
+        let mut hung_up = false;
 
         loop {
-            if SIGWINCH_RECEIVED.swap(false, Ordering::SeqCst) {
-                self.forward_winsize()?;
-            }
-            if SIGINT_RECEIVED.swap(false, Ordering::SeqCst) {
-                self.forward_signal(Signal::SIGINT);
-            }
-            if SIGTERM_RECEIVED.swap(false, Ordering::SeqCst) {
-                self.forward_signal(Signal::SIGTERM);
+            if self.handle_signals()? {
+                hung_up = true;
+                break;
             }
 
             let master_fd = unsafe { BorrowedFd::borrow_raw(self.pty_master.as_raw_fd()) };
             let stdin_borrowed = unsafe { BorrowedFd::borrow_raw(stdin_fd.as_raw_fd()) };
 
+            let mut master_events = PollFlags::POLLIN;
+            if !self.pty_input.is_empty() {
+                master_events |= PollFlags::POLLOUT;
+            }
+            // Leave stdin unread while the child is behind on input
+            let stdin_events = if self.pty_input.is_full() {
+                PollFlags::empty()
+            } else {
+                PollFlags::POLLIN
+            };
             let mut poll_fds = [
-                PollFd::new(master_fd, PollFlags::POLLIN),
-                PollFd::new(stdin_borrowed, PollFlags::POLLIN),
+                PollFd::new(master_fd, master_events),
+                PollFd::new(stdin_borrowed, stdin_events),
             ];
 
-            let poll_timeout_ms = self
-                .time_until_render()
-                .map(|d| d.as_millis().min(100) as u16)
-                .unwrap_or(100);
+            let replay_due = self.replaying_lookback_cache.then_some(Duration::ZERO);
+            let poll_timeout_ms = [
+                self.time_until_render(),
+                self.input_decoder.time_until_flush(),
+                replay_due,
+            ]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|d| d.as_millis().min(100) as u16)
+            .unwrap_or(100);
 
             match poll(&mut poll_fds, PollTimeout::from(poll_timeout_ms)) {
                 Ok(0) => {
+                    self.continue_lookback_replay(&stdout_fd)?;
+                    self.flush_input_timeout(&stdout_fd)?;
                     self.flush_pending_vt_render(&stdout_fd)?;
+                    self.expire_notice(&stdout_fd)?;
                     self.check_auto_lookback(&stdout_fd)?;
                     continue;
                 }
@@ -346,8 +414,12 @@ impl Proxy {
             }
 
             self.flush_pending_vt_render(&stdout_fd)?;
+            self.continue_lookback_replay(&stdout_fd)?;
 
             if let Some(revents) = poll_fds[0].revents() {
+                if revents.contains(PollFlags::POLLOUT) {
+                    self.pty_input.write_to(&self.pty_master)?;
+                }
                 if revents.contains(PollFlags::POLLIN) {
                     match nix_read(&self.pty_master, &mut buf) {
                         Ok(0) => break,
@@ -366,19 +438,41 @@ impl Proxy {
                 && revents.contains(PollFlags::POLLIN)
             {
                 match nix_read(&stdin_fd, &mut buf) {
-                    Ok(0) => break,
-                    Ok(n) => self.process_input(&buf[..n], &stdout_fd)?,
+                    Ok(n @ 1..) => self.process_input(&buf[..n], &stdout_fd)?,
                     Err(Errno::EAGAIN) => {}
+                    // The terminal was closed, which may be seen here before
+                    // the SIGHUP arrives
+                    Ok(0) | Err(Errno::EIO) => {
+                        self.forward_signal(Signal::SIGHUP);
+                        hung_up = true;
+                        break;
+                    }
                     Err(e) => anyhow::bail!("read from stdin failed: {}", e),
                 }
             }
         }
 
-        // Final render before exit
-        if self.vt_render_pending {
-            self.render_vt_screen(&stdout_fd)?;
+        // Nothing can be drawn once the terminal is gone
+        if !hung_up {
+            // Final render before exit
+            if self.vt_render_pending {
+                self.render_vt_screen(&stdout_fd)?;
+            }
+            // In case the child exited without turning off what it turned on
+            self.reset_input_modes(&stdout_fd)?;
         }
 
+        if let Some(path) = self.config.export_on_exit.clone() {
+            if let Err(e) = self.catch_up_lookback_cache() {
+                eprintln!("Failed to read held back output: {:#}", e);
+            }
+            if let Err(e) = self.export_history(&path) {
+                eprintln!("Failed to export history to {}: {:#}", path.display(), e);
+            }
+        }
+
+        // The session is saved when the proxy is dropped, so it's kept even
+        // if the loop above fails
         self.wait_child()
     }
 
@@ -400,54 +494,83 @@ impl Proxy {
             feed_vt
         );
 
+        if self.replaying_lookback_cache {
+            return self
+                .lookback_cache
+                .push(data)
+                .context("queueing output behind lookback output");
+        }
+
         if self.in_alternate_screen {
             // Feed VT but NOT history while in alt screen
             // Alt screen content (TUI editors, etc.) shouldn't be in lookback history
             if feed_vt {
-                self.vt_parser.process(data);
+                self.feed_vt(data);
             }
             return self.process_output_alt_screen(data, stdout_fd);
         }
 
         if self.in_lookback_mode {
             debug!("process_output: caching {} bytes for lookback", data.len());
-            self.lookback_cache.extend_from_slice(data);
-            return Ok(());
+            return self.cache_lookback_output(data);
+        }
+
+        if self.passthrough {
+            if feed_vt {
+                self.feed_vt(data);
+            }
+            if !self.vt_parser.screen().alternate_screen() {
+                // Written as is, so only the stack needs updating
+                self.keyboard_modes.scan(data);
+                self.push_to_history(data);
+            }
+            self.last_output_time = Some(Instant::now());
+            return write_all(stdout_fd, data);
         }
 
         // Feed data to VT emulator (unless already fed by caller)
         if feed_vt {
-            self.vt_parser.process(data);
+            self.feed_vt(data);
         }
+        self.forward_keyboard_modes(data, stdout_fd)?;
+        self.forward_side_channels(data, stdout_fd)?;
         self.vt_render_pending = true;
         self.last_output_time = Some(Instant::now());
 
-        // Process sync blocks for history management
+        let Some(alt_pos) = self.find_alt_screen_enter(data) else {
+            self.push_output_to_history(data);
+            return Ok(());
+        };
+        debug!(
+            "process_output: ALT_SCREEN_ENTER detected at pos={}",
+            alt_pos
+        );
+        self.push_output_to_history(&data[..alt_pos]);
+        // Add ALL remaining data to history (including alt screen enter and content)
+        // This ensures history matches VT exactly
+        let remaining = &data[alt_pos..];
+        if self.in_sync_block {
+            self.sync_buffer.extend_from_slice(remaining);
+            self.flush_sync_block_to_history();
+            self.in_sync_block = false;
+        } else {
+            self.push_to_history(remaining);
+        }
+        self.in_alternate_screen = true;
+        // The alternate screen would draw over the peek overlay, and
+        // keys go straight to the child there, so peek can't stay open
+        self.close_peek()?;
+        let seq_len = self.alt_screen_enter_len(remaining);
+        // Write alt screen enter directly
+        write_all(stdout_fd, &remaining[..seq_len])?;
+        self.process_output_alt_screen(&remaining[seq_len..], stdout_fd)
+    }
+
+    /// Add main-screen output to history, collecting sync blocks so each is
+    /// stored as a whole.
+    fn push_output_to_history(&mut self, data: &[u8]) {
         let mut pos = 0;
         while pos < data.len() {
-            // Check for alt screen enter
-            if let Some(alt_pos) = self.find_alt_screen_enter(&data[pos..]) {
-                debug!(
-                    "process_output: ALT_SCREEN_ENTER detected at pos={}",
-                    pos + alt_pos
-                );
-                // Add ALL remaining data to history (including alt screen enter and content)
-                // This ensures history matches VT exactly
-                let remaining = &data[pos..];
-                if self.in_sync_block {
-                    self.sync_buffer.extend_from_slice(remaining);
-                    self.flush_sync_block_to_history();
-                    self.in_sync_block = false;
-                } else {
-                    self.push_to_history(remaining);
-                }
-                self.in_alternate_screen = true;
-                let seq_len = self.alt_screen_enter_len(&data[pos + alt_pos..]);
-                // Write alt screen enter directly
-                self.write_to_terminal(stdout_fd, &data[pos + alt_pos..pos + alt_pos + seq_len])?;
-                return self.process_output_alt_screen(&data[pos + alt_pos + seq_len..], stdout_fd);
-            }
-
             if self.in_sync_block {
                 if let Some(idx) = self.sync_end_finder.find(&data[pos..]) {
                     debug!("process_output: SYNC_END at pos={}", pos + idx);
@@ -476,8 +599,94 @@ impl Proxy {
                 break;
             }
         }
+    }
 
-        Ok(())
+    /// Hold output back while lookback is open. Once the cache is full its
+    /// contents are caught up on in the background (VT and history, without
+    /// touching the terminal), so exiting lookback stays fast. Output that
+    /// would switch to the alternate screen has to be written to the
+    /// terminal, so from then on output spills to disk until lookback exits
+    /// instead.
+    fn cache_lookback_output(&mut self, data: &[u8]) -> Result<()> {
+        self.lookback_cache
+            .push(data)
+            .context("caching output during lookback")?;
+        if !self.lookback_cache.is_full() {
+            return Ok(());
+        }
+
+        let mut cached = self.lookback_cache.take_memory();
+        if self.find_alt_screen_enter(&cached).is_none() {
+            // What may be the start of an alternate screen switch stays in
+            // the cache, so it is found once the rest arrives
+            let keep = [ALT_SCREEN_ENTER, ALT_SCREEN_ENTER_LEGACY]
+                .into_iter()
+                .map(|marker| partial_marker_len(&cached, marker))
+                .max()
+                .unwrap_or(0);
+            let tail = cached.split_off(cached.len() - keep);
+            debug!(
+                "cache_lookback_output: catching up on {} bytes",
+                cached.len()
+            );
+            self.catch_up_output(&cached);
+            return self
+                .lookback_cache
+                .push(&tail)
+                .context("caching output during lookback");
+        }
+
+        debug!(
+            "cache_lookback_output: spilling {} bytes to disk",
+            cached.len()
+        );
+        self.lookback_cache
+            .spill()
+            .and_then(|()| self.lookback_cache.push(&cached))
+            .context("spilling lookback output to disk")
+    }
+
+    /// Process main-screen output while lookback is open: feed the VT and
+    /// history, and keep the side channels for when lookback exits. Nothing
+    /// is written to the terminal, whatever mode output is in.
+    fn catch_up_output(&mut self, data: &[u8]) {
+        self.feed_vt(data);
+        self.keyboard_modes.scan(data);
+        let sequences = self.side_channel_filter.extract(data);
+        self.caught_up_side_channels.extend_from_slice(&sequences);
+        self.vt_render_pending = true;
+        self.push_output_to_history(data);
+    }
+
+    /// Take in all output held back for lookback without writing to the
+    /// terminal, so history is complete before it is saved on exit.
+    fn catch_up_lookback_cache(&mut self) -> Result<()> {
+        self.replaying_lookback_cache = false;
+        let cached = std::mem::replace(
+            &mut self.lookback_cache,
+            LookbackCache::new(self.config.lookback_cache_bytes),
+        );
+        cached.replay(|chunk| {
+            let mut rest = chunk;
+            while !rest.is_empty() {
+                let end = if self.vt_parser.screen().alternate_screen() {
+                    // Alternate screen output only goes to the VT
+                    let end = self.find_alt_screen_exit(rest).map_or(rest.len(), |pos| {
+                        pos + self.alt_screen_exit_len(&rest[pos..])
+                    });
+                    self.feed_vt(&rest[..end]);
+                    end
+                } else {
+                    let end = self.find_alt_screen_enter(rest).map_or(rest.len(), |pos| {
+                        pos + self.alt_screen_enter_len(&rest[pos..])
+                    });
+                    self.catch_up_output(&rest[..end]);
+                    end
+                };
+                rest = &rest[end..];
+            }
+            Ok::<(), anyhow::Error>(())
+        })
     }
 
     fn process_output_alt_screen<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
@@ -486,9 +695,9 @@ impl Proxy {
                 "process_output_alt_screen: ALT_SCREEN_EXIT detected at pos={}",
                 exit_pos
             );
-            self.write_to_terminal(stdout_fd, &data[..exit_pos])?;
+            write_all(stdout_fd, &data[..exit_pos])?;
             let seq_len = self.alt_screen_exit_len(&data[exit_pos..]);
-            self.write_to_terminal(stdout_fd, &data[exit_pos..exit_pos + seq_len])?;
+            write_all(stdout_fd, &data[exit_pos..exit_pos + seq_len])?;
             self.in_alternate_screen = false;
 
             // Force full VT render to restore main screen content
@@ -508,7 +717,7 @@ impl Proxy {
             }
             return Ok(());
         }
-        self.write_to_terminal(stdout_fd, data)
+        write_all(stdout_fd, data)
     }
 
     /// Check for alt screen transitions without re-feeding VT/history
@@ -520,12 +729,42 @@ impl Proxy {
             );
             self.in_alternate_screen = true;
             let seq_len = self.alt_screen_enter_len(&data[alt_pos..]);
-            self.write_to_terminal(stdout_fd, &data[alt_pos..alt_pos + seq_len])?;
+            write_all(stdout_fd, &data[alt_pos..alt_pos + seq_len])?;
             return self.process_output_alt_screen(&data[alt_pos + seq_len..], stdout_fd);
         }
         Ok(())
     }
 
+    /// Write the Kitty keyboard protocol sequences in main-screen output to
+    /// the terminal, which the VT emulator would drop. They go out ahead of
+    /// the frame that renders the output.
+    fn forward_keyboard_modes<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
+        let main_screen = &data[..self.find_alt_screen_enter(data).unwrap_or(data.len())];
+        let sequences = self.keyboard_modes.scan(main_screen);
+        if sequences.is_empty() || self.keyboard_modes_suspended {
+            return Ok(());
+        }
+        debug!(
+            "forward_keyboard_modes: {:?} depth={}",
+            String::from_utf8_lossy(&sequences),
+            self.keyboard_modes.depth()
+        );
+        write_all(stdout_fd, &sequences)
+    }
+
+    /// Write the titles, notifications and other side channels in
+    /// main-screen output to the terminal, ahead of the frame that renders
+    /// the output.
+    fn forward_side_channels<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
+        let main_screen = &data[..self.find_alt_screen_enter(data).unwrap_or(data.len())];
+        let sequences = self.side_channel_filter.extract(main_screen);
+        if sequences.is_empty() {
+            return Ok(());
+        }
+        debug!("forward_side_channels: {} bytes", sequences.len());
+        write_all(stdout_fd, &sequences)
+    }
+
     fn find_alt_screen_enter(&self, data: &[u8]) -> Option<usize> {
         let pos1 = self.alt_screen_enter_finder.find(data);
         let pos2 = self.alt_screen_enter_legacy_finder.find(data);
@@ -564,65 +803,6 @@ impl Proxy {
         }
     }
 
-    fn kitty_mode_enabled(&self) -> bool {
-        self.kitty_mode_stack > 0
-    }
-
-    /// Write data to the terminal and track Kitty keyboard protocol state
-    fn write_to_terminal<F: AsFd>(&mut self, stdout_fd: &F, data: &[u8]) -> Result<()> {
-        Self::update_kitty_mode_helper(
-            &mut self.kitty_output_parser,
-            &mut self.kitty_mode_stack,
-            self.kitty_mode_supported,
-            data,
-        );
-        write_all(stdout_fd, data)
-    }
-
-    fn update_kitty_mode_helper(
-        parser: &mut TermwizParser,
-        stack: &mut u32,
-        supported: bool,
-        data: &[u8],
-    ) {
-        let actions = parser.parse_as_vec(data);
-        for action in actions {
-            if let Action::CSI(csi) = action {
-                match csi {
-                    CSI::Keyboard(Keyboard::PushKittyState { flags, .. }) => {
-                        if supported {
-                            *stack = stack.saturating_add(1);
-                            debug!(
-                                "Kitty keyboard protocol push (flags={:?}, stack={})",
-                                flags, stack
-                            );
-                        }
-                    }
-                    CSI::Keyboard(Keyboard::SetKittyState { flags, .. }) => {
-                        if supported && !flags.is_empty() && *stack == 0 {
-                            *stack = 1;
-                            debug!(
-                                "Kitty keyboard protocol set (flags={:?}, stack={})",
-                                flags, stack
-                            );
-                        } else if flags.is_empty() && *stack > 0 {
-                            debug!("Kitty keyboard protocol set empty flags (stack={})", stack);
-                        }
-                    }
-                    CSI::Keyboard(Keyboard::PopKittyState(n)) => {
-                        let prev = *stack;
-                        *stack = stack.saturating_sub(n);
-                        debug!(
-                            "Kitty keyboard protocol pop {} (stack {} -> {})",
-                            n, prev, stack
-                        );
-                    }
-                    _ => {}
-                }
-            }
-        }
-    }
-
     fn flush_sync_block_to_history(&mut self) {
         let has_clear_screen = self.clear_screen_finder.find(&self.sync_buffer).is_some();
         let has_cursor_home = self.cursor_home_finder.find(&self.sync_buffer).is_some();
@@ -634,50 +814,151 @@ impl Proxy {
             is_full_redraw
         );
 
-        if is_full_redraw {
-            debug!("CLEARING HISTORY");
-            self.history.clear();
-            // Re-seed with clear screen after clearing
-            self.history.push_bytes(CLEAR_SCREEN);
-            self.history.push_bytes(CURSOR_HOME);
+        if is_full_redraw && self.config.history_mode == HistoryMode::Raw {
+            self.start_history_generation();
+            self.frame_dedup.reset();
+        }
+        if !self.push_frame_to_history() {
+            self.push_to_history(&self.sync_buffer.clone());
         }
-        self.push_to_history(&self.sync_buffer.clone());
         self.sync_buffer.clear();
     }
 
+    /// Store only the lines of the sync block that weren't in the previous
+    /// one. Returns false if the block should be stored as is instead.
+    fn push_frame_to_history(&mut self) -> bool {
+        if !self.config.dedupe_frames || self.config.history_mode != HistoryMode::Raw {
+            return false;
+        }
+        let (rows, cols) = self.vt_parser.screen().size();
+        let mut lines = Vec::new();
+        if !self
+            .frame_dedup
+            .push_frame(&self.sync_buffer, rows, cols, &mut lines)
+        {
+            return false;
+        }
+        debug!(
+            "push_frame_to_history: block_len={} stored_len={}",
+            self.sync_buffer.len(),
+            lines.len()
+        );
+        // Start on a fresh line unless history ends at the cursor home seed
+        let partial = self.history.partial_line();
+        if !lines.is_empty() && !partial.is_empty() && !partial.ends_with(CURSOR_HOME) {
+            self.history.push_bytes(b"\r\n");
+        }
+        self.history.push_bytes(&lines);
+        true
+    }
+
+    /// Re-seed history with clear screen, keeping the old generation for
+    /// lookback.
+    fn start_history_generation(&mut self) {
+        debug!("STARTING NEW HISTORY GENERATION");
+        let mut generation = LineBuffer::new(self.config.max_history_lines)
+            .with_max_bytes(self.config.max_history_bytes);
+        generation.push_bytes(CLEAR_SCREEN);
+        generation.push_bytes(CURSOR_HOME);
+        let mut previous = std::mem::replace(&mut self.history, generation);
+        if self.config.history_mode == HistoryMode::Screen {
+            let mut screen = Vec::new();
+            screen_history::append_screen(self.vt_parser.screen(), &mut screen);
+            previous.push_bytes(&screen);
+        }
+        if previous.total_bytes() > CLEAR_SCREEN.len() + CURSOR_HOME.len() {
+            self.history_generations.retire(previous);
+        }
+    }
+
     /// Push data to history, filtering out terminal query sequences that would
-    /// cause the terminal to respond when replayed.
+    /// cause the terminal to respond when replayed. In screen mode history is
+    /// captured from the VT emulator instead.
     fn push_to_history(&mut self, data: &[u8]) {
+        if self.config.history_mode == HistoryMode::Screen {
+            return;
+        }
         let filtered = self.history_filter.filter(data);
         self.history.push_bytes(&filtered);
     }
 
-    fn flush_pending_vt_render<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
-        if !self.vt_render_pending || self.in_lookback_mode || self.in_alternate_screen {
-            return Ok(());
+    /// Feed output to the VT emulator, marking the cells written inside OSC 8
+    /// links since the emulator drops them.
+    fn feed_vt(&mut self, data: &[u8]) {
+        for piece in self.hyperlinks.split(data) {
+            match piece {
+                Piece::Output(bytes) => {
+                    let from = self.vt_parser.screen().cursor_position();
+                    self.feed_vt_output(&bytes);
+                    self.hyperlinks.mark(self.vt_parser.screen(), from);
+                }
+                Piece::Link(link) => self.hyperlinks.set_link(link),
+            }
         }
+    }
 
-        let elapsed = self
-            .last_output_time
-            .map(|t| t.elapsed())
-            .unwrap_or(Duration::MAX);
+    /// In screen mode, rows scrolling off the top are captured into history,
+    /// and clearing the screen starts a new history generation since the
+    /// cleared rows never reach the scrollback.
+    fn feed_vt_output(&mut self, data: &[u8]) {
+        if self.config.history_mode == HistoryMode::Raw {
+            self.vt_parser.process(data);
+            return;
+        }
+        let mut rest = data;
+        while let Some(idx) = self.clear_screen_finder.find(rest) {
+            screen_history::process(&mut self.vt_parser, &rest[..idx], &mut self.history);
+            if !self.vt_parser.screen().alternate_screen() {
+                self.start_history_generation();
+            }
+            let clear_end = idx + CLEAR_SCREEN.len();
+            screen_history::process(
+                &mut self.vt_parser,
+                &rest[idx..clear_end],
+                &mut self.history,
+            );
+            rest = &rest[clear_end..];
+        }
+        screen_history::process(&mut self.vt_parser, rest, &mut self.history);
+    }
 
-        // Wait longer if in sync block (more data likely coming)
-        let delay = if self.in_sync_block {
-            Duration::from_millis(SYNC_BLOCK_DELAY_MS)
-        } else {
-            Duration::from_millis(RENDER_DELAY_MS)
-        };
+    /// Append the current history generation to the output buffer. In screen
+    /// mode that is the captured rows followed by what is on screen now.
+    fn append_current_history(&mut self) {
+        self.history.append_all(&mut self.output_buffer);
+        if self.config.history_mode == HistoryMode::Screen {
+            screen_history::append_screen(self.vt_parser.screen(), &mut self.output_buffer);
+        }
+    }
 
-        if elapsed >= delay {
-            self.render_vt_screen(stdout_fd)?;
+    /// Append every history generation to the output buffer, oldest first,
+    /// for saving and exporting.
+    fn append_all_history(&mut self) {
+        self.history_generations.append_view(
+            HistoryView::Joined,
+            &self.history,
+            &mut self.output_buffer,
+        );
+        if self.config.history_mode == HistoryMode::Screen {
+            screen_history::append_screen(self.vt_parser.screen(), &mut self.output_buffer);
         }
+    }
 
+    fn flush_pending_vt_render<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        if self.time_until_render() == Some(Duration::ZERO) {
+            self.render_vt_screen(stdout_fd)?;
+        }
         Ok(())
     }
 
+    /// Time until the next frame may be rendered. A frame is due once output
+    /// has settled for the render delay and the refresh-rate limit allows it.
     fn time_until_render(&self) -> Option<Duration> {
-        if !self.vt_render_pending || self.in_lookback_mode || self.in_alternate_screen {
+        if !self.vt_render_pending
+            || self.in_lookback_mode
+            || self.in_alternate_screen
+            || self.passthrough
+        {
             return None;
         }
 
@@ -686,37 +967,89 @@ impl Proxy {
             .map(|t| t.elapsed())
             .unwrap_or(Duration::MAX);
 
+        // Wait longer if in sync block (more data likely coming)
         let delay = if self.in_sync_block {
             Duration::from_millis(SYNC_BLOCK_DELAY_MS)
         } else {
             Duration::from_millis(RENDER_DELAY_MS)
         };
 
-        if elapsed >= delay {
-            Some(Duration::ZERO)
-        } else {
-            Some(delay - elapsed)
-        }
+        let settle = delay.saturating_sub(elapsed);
+        let throttle = self
+            .redraw_throttler
+            .time_until_can_render()
+            .unwrap_or(Duration::ZERO);
+
+        Some(settle.max(throttle))
     }
 
     fn render_vt_screen<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
         let is_diff = self.vt_prev_screen.is_some();
         self.output_buffer.clear();
         self.output_buffer.extend_from_slice(SYNC_START);
+        if self.keyboard_modes_suspended {
+            self.keyboard_modes_suspended = false;
+            self.output_buffer
+                .extend_from_slice(&self.keyboard_modes.push_all());
+        }
 
+        // Input modes the child set (bracketed paste, application cursor
+        // keys, mouse reporting) go along with the screen contents
+        let screen = self.vt_parser.screen();
         match &self.vt_prev_screen {
             Some(prev) => {
                 // Diff-based render: only send changes
                 self.output_buffer
-                    .extend_from_slice(&self.vt_parser.screen().contents_diff(prev));
+                    .extend_from_slice(&screen.contents_diff(prev));
+                self.output_buffer
+                    .extend_from_slice(&screen.input_mode_diff(prev));
             }
             None => {
                 // First render: full screen
                 self.output_buffer
-                    .extend_from_slice(&self.vt_parser.screen().contents_formatted());
+                    .extend_from_slice(&screen.contents_formatted());
+                self.output_buffer
+                    .extend_from_slice(&screen.input_mode_formatted());
             }
         }
 
+        // Linked runs are drawn again over the emulator's output with their
+        // OSC 8 links
+        let cwd = self.linkifier.as_ref().map(|_| self.child_cwd());
+        let found = match (&mut self.linkifier, cwd) {
+            (Some(linkifier), Some(cwd)) => linkifier.find(self.vt_parser.screen(), &cwd),
+            _ => Vec::new(),
+        };
+        let mut restyle = self.hyperlinks.render(
+            self.vt_parser.screen(),
+            self.vt_prev_screen.is_none(),
+            found,
+            &mut self.output_buffer,
+        );
+
+        if self.in_peek_mode {
+            let hint = self.pager_hint();
+            if let Some(pager) = self.pager.as_mut() {
+                pager.render_overlay(&hint, &mut self.output_buffer);
+            }
+        }
+        if let Some((message, _)) = &self.notice {
+            let (rows, cols) = self.vt_parser.screen().size();
+            let text: String = message
+                .chars()
+                .take(usize::from(cols).saturating_sub(2))
+                .collect();
+            self.output_buffer.extend_from_slice(
+                format!("\x1b[{};1H\x1b[m\x1b[2K\x1b[7m {} \x1b[m", rows, text).as_bytes(),
+            );
+            restyle = true;
+        }
+        // The next diff assumes the terminal has the screen's attributes
+        if restyle {
+            self.output_buffer
+                .extend_from_slice(&self.vt_parser.screen().attributes_formatted());
+        }
+
         self.output_buffer
             .extend_from_slice(&self.vt_parser.screen().cursor_state_formatted());
         self.output_buffer.extend_from_slice(SYNC_END);
@@ -726,28 +1059,32 @@ impl Proxy {
             is_diff,
             self.output_buffer.len()
         );
-        // Can't use write_to_terminal here due to borrow checker - can't pass
-        // &self.output_buffer while also taking &mut self
-        Self::update_kitty_mode_helper(
-            &mut self.kitty_output_parser,
-            &mut self.kitty_mode_stack,
-            self.kitty_mode_supported,
-            &self.output_buffer,
-        );
         write_all(stdout_fd, &self.output_buffer)?;
 
         // Store current screen for next diff
         self.vt_prev_screen = Some(self.vt_parser.screen().clone());
         self.vt_render_pending = false;
         self.last_render_time = Some(Instant::now());
+        self.redraw_throttler.mark_rendered();
         Ok(())
     }
 
+    /// The child's working directory, for resolving file references: the
+    /// last one it reported with OSC 7, or else the one the system knows.
+    fn child_cwd(&self) -> PathBuf {
+        if let Some(cwd) = self.side_channel_filter.cwd() {
+            return cwd.to_path_buf();
+        }
+        fs::read_link(format!("/proc/{}/cwd", self.child.id()))
+            .or_else(|_| std::env::current_dir())
+            .unwrap_or_default()
+    }
+
     fn check_auto_lookback<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
         if self.auto_lookback_timeout.is_zero() {
             return Ok(());
         }
-        if self.in_lookback_mode || self.in_alternate_screen {
+        if self.in_lookback_mode || self.in_peek_mode || self.in_alternate_screen {
             return Ok(());
         }
 
@@ -787,12 +1124,13 @@ impl Proxy {
 
     fn dump_history<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
         debug!(
-            "dump_history: history_bytes={} lines={}",
+            "dump_history: history_bytes={} resident_bytes={} lines={}",
             self.history.total_bytes(),
+            self.history.resident_bytes(),
             self.history.line_count()
         );
         self.output_buffer.clear();
-        self.history.append_all(&mut self.output_buffer);
+        self.append_current_history();
 
         // Debug: write history to file if CLAUDE_CHILL_HISTORY_FILE is set
         if let Ok(path) = std::env::var("CLAUDE_CHILL_HISTORY_FILE")
@@ -801,16 +1139,8 @@ impl Proxy {
             debug!("Failed to write history file: {}", e);
         }
 
-        self.write_to_terminal(stdout_fd, CLEAR_SCREEN)?;
-        self.write_to_terminal(stdout_fd, CURSOR_HOME)?;
-        // Can't use write_to_terminal here due to borrow checker - can't pass
-        // &self.output_buffer while also taking &mut self
-        Self::update_kitty_mode_helper(
-            &mut self.kitty_output_parser,
-            &mut self.kitty_mode_stack,
-            self.kitty_mode_supported,
-            &self.output_buffer,
-        );
+        write_all(stdout_fd, CLEAR_SCREEN)?;
+        write_all(stdout_fd, CURSOR_HOME)?;
         write_all(stdout_fd, &self.output_buffer)?;
 
         // Force full VT render on next output since terminal now shows history
@@ -818,85 +1148,617 @@ impl Proxy {
         Ok(())
     }
 
+    /// Write history to `path` in the format implied by its extension.
+    fn export_history(&mut self, path: &Path) -> Result<()> {
+        debug!(
+            "export_history: path={} history_bytes={}",
+            path.display(),
+            self.history.total_bytes()
+        );
+        self.output_buffer.clear();
+        self.append_all_history();
+        let cols = self.vt_parser.screen().size().1;
+        export::write_export(path, &self.output_buffer, cols)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Write history to the session directory so it can be browsed with
+    /// `claude-chill sessions` after the proxy exits.
+    fn save_session(&mut self) -> Result<()> {
+        let (Some(dir), Some(mut session)) = (self.config.session_dir.clone(), self.session.take())
+        else {
+            return Ok(());
+        };
+        self.catch_up_lookback_cache()
+            .context("reading held back output")?;
+        session.end = sessions::unix_now();
+        session.cols = self.vt_parser.screen().size().1;
+        self.output_buffer.clear();
+        self.append_all_history();
+        debug!(
+            "save_session: id={} history_bytes={}",
+            session.id,
+            self.output_buffer.len()
+        );
+        let store = SessionStore::new(dir);
+        store.save(&session, &self.output_buffer)?;
+        store.prune(self.config.max_sessions)
+    }
+
+    /// The configured export path, or a timestamped file in the working
+    /// directory.
+    fn default_export_path(&self) -> PathBuf {
+        self.config
+            .export_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("claude-chill-{}.txt", sessions::unix_now())))
+    }
+
+    /// Export from lookback mode to the configured path, or a timestamped
+    /// file in the working directory. Returns a message for the user.
+    fn export_from_lookback(&mut self) -> String {
+        let path = self.default_export_path();
+        self.output_buffer.clear();
+        self.append_lookback_history();
+        let cols = self.vt_parser.screen().size().1;
+        match export::write_export(&path, &self.output_buffer, cols) {
+            Ok(()) => format!("exported history to {}", path.display()),
+            Err(e) => format!("export failed: {:#}", e),
+        }
+    }
+
+    /// Append the history generation(s) lookback is showing to the output
+    /// buffer.
+    fn append_lookback_history(&mut self) {
+        self.history_generations.append_view(
+            self.history_view,
+            &self.history,
+            &mut self.output_buffer,
+        );
+        let shows_current = matches!(
+            self.history_view,
+            HistoryView::Generation(0) | HistoryView::Joined
+        );
+        if shows_current && self.config.history_mode == HistoryMode::Screen {
+            screen_history::append_screen(self.vt_parser.screen(), &mut self.output_buffer);
+        }
+    }
+
+    fn switch_history_view(&mut self, command: PagerCommand) {
+        self.history_view = match command {
+            PagerCommand::OlderGeneration => self.history_generations.older(self.history_view),
+            PagerCommand::NewerGeneration => self.history_generations.newer(self.history_view),
+            _ => HistoryView::Joined,
+        };
+        debug!("switch_history_view: view={:?}", self.history_view);
+    }
+
     fn process_input<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
         self.last_stdin_time = Some(Instant::now());
 
         debug!("process_input: stdin={:?}", data);
 
         if self.in_alternate_screen {
-            return write_all(&self.pty_master, data);
+            let mut input = self.input_decoder.take_pending();
+            input.extend_from_slice(data);
+            return self.pty_input.send(&self.pty_master, &input);
         }
 
-        let lookback_sequence = if self.kitty_mode_enabled() {
-            self.config.lookback_sequence_kitty.clone()
-        } else {
-            self.config.lookback_sequence_legacy.clone()
-        };
+        let keys = self.input_decoder.decode(data);
+        self.handle_keys(keys, stdout_fd)
+    }
 
-        for &byte in data {
-            if self.in_lookback_mode && byte == 0x03 {
-                self.lookback_input_buffer.clear();
-                self.exit_lookback_mode(stdout_fd)?;
+    /// Pass on input held back for the rest of an escape sequence that never
+    /// came.
+    fn flush_input_timeout<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        if self.input_decoder.time_until_flush() != Some(Duration::ZERO) {
+            return Ok(());
+        }
+        let keys = self.input_decoder.flush();
+        debug!("flush_input_timeout: keys={:?}", keys);
+        self.handle_keys(keys, stdout_fd)?;
+        // The pager holds a lone ESC back too, until told nothing follows
+        match self.pager.as_mut() {
+            Some(pager) => {
+                let command = pager.flush_input();
+                self.run_pager_command(command, stdout_fd)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn handle_keys<F: AsFd>(&mut self, keys: Vec<KeyInput>, stdout_fd: &F) -> Result<()> {
+        let mut input = Vec::new();
+
+        for key in keys {
+            if key.paste {
+                // A paste cancels a pending chord and is never a hotkey
+                self.chord_prefix = None;
+                self.flush_input(&mut input, stdout_fd)?;
+                self.forward_paste(&key.bytes, stdout_fd)?;
+                continue;
+            }
+            // Releases, repeats and bare modifier presses go on as usual
+            // without ending a pending chord
+            if key.key.is_some()
+                && let Some(prefix) = self.chord_prefix.take()
+            {
+                self.finish_chord(prefix, key, &mut input, stdout_fd)?;
                 continue;
             }
+            match self.config.keymap.match_key(&key) {
+                KeyMatch::Complete(action) => {
+                    // Typing before the key is handled before it
+                    self.flush_input(&mut input, stdout_fd)?;
+                    if !self.run_key_action(action, stdout_fd)? {
+                        self.type_key(&key, &mut input, stdout_fd)?;
+                    }
+                }
+                KeyMatch::Prefix => self.chord_prefix = Some(key),
+                KeyMatch::None => {
+                    if !self.run_dump_lookback_command(&key, &mut input, stdout_fd)? {
+                        self.type_key(&key, &mut input, stdout_fd)?;
+                    }
+                }
+            }
+        }
 
-            let lookback_action = self.check_sequence_match(
-                byte,
-                &mut self.lookback_input_buffer.clone(),
-                &lookback_sequence,
-            );
+        self.flush_input(&mut input, stdout_fd)
+    }
+
+    /// Add a key press to the typing in `input`. A confirm key on the live
+    /// screen only goes through if it was pressed just before; the first
+    /// press shows a notice instead.
+    fn type_key<F: AsFd>(
+        &mut self,
+        key: &KeyInput,
+        input: &mut Vec<u8>,
+        stdout_fd: &F,
+    ) -> Result<()> {
+        let Some(pressed) = key
+            .key
+            .as_ref()
+            .filter(|_| self.config.keymap.is_guarded(key))
+            .filter(|_| !self.in_lookback_mode && !self.in_peek_mode)
+        else {
+            input.extend_from_slice(&key.bytes);
+            return Ok(());
+        };
+        let now = Instant::now();
+        match self.confirm_pending.take() {
+            Some((pending, expires)) if pending == *pressed && now < expires => {
+                debug!("type_key: {} confirmed", pressed);
+                input.extend_from_slice(&key.bytes);
+                if let Some((_, notice_expires)) = &mut self.notice {
+                    *notice_expires = now;
+                }
+                self.expire_notice(stdout_fd)
+            }
+            _ => {
+                let timeout = Duration::from_millis(self.config.confirm_timeout_ms);
+                let message = confirm_message(pressed);
+                self.confirm_pending = Some((pressed.clone(), now + timeout));
+                // Typing before the key still goes through
+                self.flush_input(input, stdout_fd)?;
+                self.show_notice_for(message, timeout, stdout_fd)
+            }
+        }
+    }
 
-            self.lookback_input_buffer.push(byte);
+    /// Handle the key pressed after a chord prefix. The prefix pressed twice
+    /// is sent through as typing; a key that completes no chord is dropped
+    /// along with the prefix, as in tmux.
+    fn finish_chord<F: AsFd>(
+        &mut self,
+        prefix: KeyInput,
+        key: KeyInput,
+        input: &mut Vec<u8>,
+        stdout_fd: &F,
+    ) -> Result<()> {
+        let (Some(prefix_key), Some(pressed)) = (&prefix.key, &key.key) else {
+            debug!("finish_chord: dropping {:?} after prefix", key.bytes);
+            return Ok(());
+        };
+        if pressed == prefix_key {
+            input.extend_from_slice(&key.bytes);
+            return Ok(());
+        }
+        let Some(action) = self.config.keymap.match_chord(&prefix, &key) else {
+            debug!("finish_chord: no chord for {:?} after prefix", key.bytes);
+            return Ok(());
+        };
+        self.flush_input(input, stdout_fd)?;
+        if !self.run_key_action(action, stdout_fd)? {
+            input.extend_from_slice(&prefix.bytes);
+            input.extend_from_slice(&key.bytes);
+        }
+        Ok(())
+    }
 
-            if self.lookback_input_buffer.len() > lookback_sequence.len() {
-                let excess = self.lookback_input_buffer.len() - lookback_sequence.len();
-                self.lookback_input_buffer.drain(..excess);
+    /// Send input that isn't a hotkey to wherever the current mode sends
+    /// typing: the pager, dump-mode lookback or the child.
+    fn flush_input<F: AsFd>(&mut self, input: &mut Vec<u8>, stdout_fd: &F) -> Result<()> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        let input = std::mem::take(input);
+        if !self.in_lookback_mode && !self.in_peek_mode {
+            self.pty_input.send(&self.pty_master, &input)
+        } else if self.pager.is_some() {
+            self.handle_pager_input(&input, stdout_fd)
+        } else {
+            self.apply_lookback_input(&input, stdout_fd)
+        }
+    }
+
+    /// Send part of a bracketed paste on as is. In lookback and peek it
+    /// follows `lookback_input` like typing, but never runs a command.
+    fn forward_paste<F: AsFd>(&mut self, paste: &[u8], stdout_fd: &F) -> Result<()> {
+        if !self.in_lookback_mode && !self.in_peek_mode {
+            self.pty_input.send(&self.pty_master, paste)
+        } else if self.pager.is_some() {
+            self.apply_pager_paste(paste, stdout_fd)
+        } else {
+            self.apply_lookback_input(paste, stdout_fd)
+        }
+    }
+
+    /// Handle a paste in the pager or peek according to `lookback_input`,
+    /// saying what happened to it in the pager's status line.
+    fn apply_pager_paste<F: AsFd>(&mut self, paste: &[u8], stdout_fd: &F) -> Result<()> {
+        debug!(
+            "apply_pager_paste: policy={:?} len={}",
+            self.config.lookback_input,
+            paste.len()
+        );
+        let message = match self.config.lookback_input {
+            LookbackInput::Discard => "paste discarded".to_string(),
+            LookbackInput::Queue => {
+                self.queued_input.extend_from_slice(paste);
+                format!("queued: {}", input_preview(&self.queued_input, 40))
+            }
+            LookbackInput::Exit => {
+                if self.in_peek_mode {
+                    self.exit_peek_mode(stdout_fd)?;
+                } else {
+                    self.exit_lookback_mode(stdout_fd)?;
+                }
+                return self.pty_input.send(&self.pty_master, paste);
             }
+        };
+        if let Some(pager) = self.pager.as_mut() {
+            pager.set_message(message);
+        }
+        self.render_pager(stdout_fd)
+    }
 
-            match lookback_action {
-                SequenceMatch::Complete => {
-                    self.lookback_input_buffer.clear();
-                    if self.in_lookback_mode {
-                        self.exit_lookback_mode(stdout_fd)?;
-                    } else {
-                        self.enter_lookback_mode()?;
+    /// Run the action bound to a hotkey. Returns false if the action doesn't
+    /// apply in the current mode, in which case the key is treated as typing.
+    fn run_key_action<F: AsFd>(&mut self, action: KeyAction, stdout_fd: &F) -> Result<bool> {
+        debug!(
+            "run_key_action: action={} in_lookback={} in_peek={}",
+            action, self.in_lookback_mode, self.in_peek_mode
+        );
+        match action {
+            KeyAction::ToggleLookback if self.in_lookback_mode => {
+                self.exit_lookback_mode(stdout_fd)?
+            }
+            KeyAction::ToggleLookback => {
+                self.close_peek()?;
+                self.enter_lookback_mode()?;
+            }
+            KeyAction::TogglePeek if self.in_peek_mode => self.exit_peek_mode(stdout_fd)?,
+            // Peek is a smaller lookback, so there's nothing to do in lookback
+            KeyAction::TogglePeek if self.in_lookback_mode => {}
+            KeyAction::TogglePeek => self.enter_peek_mode(stdout_fd)?,
+            KeyAction::ExitLookback if self.in_peek_mode => self.exit_peek_mode(stdout_fd)?,
+            KeyAction::ExitLookback if self.in_lookback_mode => {
+                self.exit_lookback_mode(stdout_fd)?
+            }
+            KeyAction::ExitLookback => return Ok(false),
+            KeyAction::ExportHistory if self.in_lookback_mode => {
+                let message = self.export_from_lookback();
+                match self.pager.as_mut() {
+                    Some(pager) => {
+                        pager.set_message(message);
+                        self.render_pager(stdout_fd)?;
                     }
-                    continue;
-                }
-                SequenceMatch::Partial => {
-                    // Still might be lookback sequence, don't forward yet
-                    continue;
-                }
-                SequenceMatch::None => {
-                    // Not a lookback sequence - forward all buffered bytes
-                    if !self.in_lookback_mode {
-                        write_all(&self.pty_master, &self.lookback_input_buffer)?;
+                    None => {
+                        let line = format!("\r\n\x1b[7m--- {} ---\x1b[0m\r\n", message);
+                        write_all(stdout_fd, line.as_bytes())?;
                     }
-                    self.lookback_input_buffer.clear();
                 }
             }
+            // The rest act on the live screen
+            _ if self.in_lookback_mode || self.in_peek_mode => return Ok(false),
+            KeyAction::DumpHistory => {
+                self.dump_history(stdout_fd)?;
+                self.render_vt_screen(stdout_fd)?;
+            }
+            KeyAction::ExportHistory => {
+                let path = self.default_export_path();
+                let message = match self.export_history(&path) {
+                    Ok(()) => format!("exported history to {}", path.display()),
+                    Err(e) => format!("export failed: {:#}", e),
+                };
+                self.show_notice(message, stdout_fd)?;
+            }
+            KeyAction::ForceRedraw => {
+                self.vt_prev_screen = None;
+                self.render_vt_screen(stdout_fd)?;
+            }
+            KeyAction::TogglePassthrough => self.toggle_passthrough(stdout_fd)?,
+            KeyAction::ClearHistory => {
+                self.clear_history();
+                self.show_notice("history cleared".to_string(), stdout_fd)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Switch between rendering output through the VT emulator and writing
+    /// it to the terminal as is. The emulator and history keep being fed in
+    /// passthrough, so rendering can pick up where the terminal is.
+    fn toggle_passthrough<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        if self.passthrough {
+            self.passthrough = false;
+            self.in_alternate_screen = self.vt_parser.screen().alternate_screen();
+            if !self.in_alternate_screen {
+                self.vt_prev_screen = None;
+                self.show_notice("raw passthrough off".to_string(), stdout_fd)?;
+            }
+            return Ok(());
+        }
+        // Bring the terminal up to date before output bypasses the emulator
+        self.show_notice("raw passthrough on".to_string(), stdout_fd)?;
+        if self.in_sync_block {
+            self.flush_sync_block_to_history();
+            self.in_sync_block = false;
+        }
+        self.passthrough = true;
+        Ok(())
+    }
+
+    /// Render output again while lookback or peek is open, since raw output
+    /// would be written over it.
+    fn suspend_passthrough(&mut self) {
+        if self.passthrough {
+            debug!("suspend_passthrough");
+            self.passthrough = false;
+            self.passthrough_suspended = true;
+            self.in_alternate_screen = self.vt_parser.screen().alternate_screen();
+            self.vt_prev_screen = None;
+        }
+    }
+
+    /// Go back to passthrough once the terminal shows the live screen again.
+    fn resume_passthrough(&mut self) {
+        if self.passthrough_suspended {
+            debug!("resume_passthrough");
+            self.passthrough_suspended = false;
+            if self.in_sync_block {
+                self.flush_sync_block_to_history();
+                self.in_sync_block = false;
+            }
+            self.passthrough = true;
+        }
+    }
+
+    /// Drop all history, including earlier generations.
+    fn clear_history(&mut self) {
+        debug!(
+            "clear_history: history_bytes={} generations={}",
+            self.history.total_bytes(),
+            self.history_generations.count()
+        );
+        self.history = LineBuffer::new(self.config.max_history_lines)
+            .with_max_bytes(self.config.max_history_bytes);
+        self.history.push_bytes(CLEAR_SCREEN);
+        self.history.push_bytes(CURSOR_HOME);
+        self.history_generations = HistoryGenerations::new(self.config.max_history_generations);
+        self.history_view = HistoryView::default();
+        self.frame_dedup.reset();
+    }
+
+    /// Show a message over the bottom row for a couple of seconds.
+    fn show_notice<F: AsFd>(&mut self, message: String, stdout_fd: &F) -> Result<()> {
+        self.show_notice_for(message, Duration::from_millis(NOTICE_MS), stdout_fd)
+    }
+
+    fn show_notice_for<F: AsFd>(
+        &mut self,
+        message: String,
+        duration: Duration,
+        stdout_fd: &F,
+    ) -> Result<()> {
+        debug!("show_notice: {}", message);
+        self.notice = Some((message, Instant::now() + duration));
+        self.render_vt_screen(stdout_fd)
+    }
+
+    fn expire_notice<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        let Some((_, expires)) = &self.notice else {
+            return Ok(());
+        };
+        if Instant::now() < *expires {
+            return Ok(());
+        }
+        self.notice = None;
+        if self.in_lookback_mode || self.in_alternate_screen {
+            return Ok(());
+        }
+        // Full render to replace the notice row
+        self.vt_prev_screen = None;
+        self.render_vt_screen(stdout_fd)
+    }
+
+    /// Run the dump-mode lookback command bound to a key press, unless
+    /// typing has already been queued. Returns false if the key isn't one,
+    /// in which case it is typing.
+    fn run_dump_lookback_command<F: AsFd>(
+        &mut self,
+        key: &KeyInput,
+        input: &mut Vec<u8>,
+        stdout_fd: &F,
+    ) -> Result<bool> {
+        let Some(KeyCombination {
+            code: KeyCode::Char(c @ ('s' | '[' | ']' | 'a')),
+            modifiers,
+        }) = &key.key
+        else {
+            return Ok(false);
+        };
+        if !self.in_lookback_mode || self.pager.is_some() || *modifiers != Modifiers::default() {
+            return Ok(false);
+        }
+        // Typing before the key may queue input or leave lookback
+        self.flush_input(input, stdout_fd)?;
+        if !self.in_lookback_mode || !self.queued_input.is_empty() {
+            return Ok(false);
+        }
+        self.handle_dump_lookback_key(*c, stdout_fd)?;
+        Ok(true)
+    }
+
+    /// Handle typing in dump-mode lookback according to `lookback_input`.
+    fn apply_lookback_input<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
+        debug!(
+            "apply_lookback_input: policy={:?} len={}",
+            self.config.lookback_input,
+            data.len()
+        );
+        match self.config.lookback_input {
+            LookbackInput::Discard => Ok(()),
+            LookbackInput::Queue => {
+                self.queued_input.extend_from_slice(data);
+                self.show_queued_input(stdout_fd)
+            }
+            LookbackInput::Exit => {
+                self.exit_lookback_mode(stdout_fd)?;
+                self.pty_input.send(&self.pty_master, data)
+            }
+        }
+    }
+
+    /// Rewrite the line below the lookback banner with a preview of the
+    /// queued input.
+    fn show_queued_input<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        let cols = get_terminal_size().map_or(80, |ws| usize::from(ws.ws_col));
+        let preview = input_preview(&self.queued_input, cols.saturating_sub(12));
+        let line = format!("\r\x1b[2K\x1b[7m queued: {} \x1b[0m", preview);
+        write_all(stdout_fd, line.as_bytes())
+    }
+
+    /// Keys in dump-mode lookback, a subset of the pager's bindings.
+    fn handle_dump_lookback_key<F: AsFd>(&mut self, c: char, stdout_fd: &F) -> Result<()> {
+        let command = match c {
+            's' => {
+                let message = self.export_from_lookback();
+                let line = format!("\r\n\x1b[7m--- {} ---\x1b[0m\r\n", message);
+                return write_all(stdout_fd, line.as_bytes());
+            }
+            '[' => PagerCommand::OlderGeneration,
+            ']' => PagerCommand::NewerGeneration,
+            'a' => PagerCommand::AllGenerations,
+            _ => return Ok(()),
+        };
+        self.switch_history_view(command);
+        self.dump_lookback(stdout_fd)
+    }
+
+    fn handle_pager_input<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
+        let Some(pager) = self.pager.as_mut() else {
+            return Ok(());
+        };
+        let command = pager.handle_input(data);
+        self.run_pager_command(command, stdout_fd)
+    }
+
+    fn run_pager_command<F: AsFd>(
+        &mut self,
+        command: Option<PagerCommand>,
+        stdout_fd: &F,
+    ) -> Result<()> {
+        match command {
+            Some(PagerCommand::Quit) if self.in_peek_mode => return self.exit_peek_mode(stdout_fd),
+            Some(PagerCommand::Quit) => return self.exit_lookback_mode(stdout_fd),
+            Some(PagerCommand::Export) => {
+                let message = self.export_from_lookback();
+                if let Some(pager) = self.pager.as_mut() {
+                    pager.set_message(message);
+                }
+            }
+            Some(command) => {
+                self.switch_history_view(command);
+                self.open_pager()?;
+            }
+            None => {}
         }
+        self.render_pager(stdout_fd)
+    }
+
+    /// Replay history into a fresh pager sized to the current terminal, or
+    /// to the top part of it in peek mode.
+    fn open_pager(&mut self) -> Result<()> {
+        let winsize = get_terminal_size()?;
+        self.output_buffer.clear();
+        self.append_lookback_history();
+        let pager = if self.in_peek_mode {
+            let rows = (winsize.ws_row / PEEK_ROWS_DIVISOR).max(2);
+            Pager::new(&self.output_buffer, rows, winsize.ws_col).with_title("PEEK")
+        } else {
+            Pager::new(&self.output_buffer, winsize.ws_row, winsize.ws_col)
+        };
+        self.pager = Some(pager);
         Ok(())
     }
 
-    fn check_sequence_match(
-        &self,
-        byte: u8,
-        buffer: &mut Vec<u8>,
-        sequence: &[u8],
-    ) -> SequenceMatch {
-        buffer.push(byte);
-        if buffer.len() > sequence.len() {
-            let excess = buffer.len() - sequence.len();
-            buffer.drain(..excess);
-        }
-        if buffer.as_slice() == sequence {
-            SequenceMatch::Complete
-        } else if sequence.starts_with(buffer) {
-            SequenceMatch::Partial
+    /// The keys that leave the current mode, e.g. "[ctrl][6] or [ctrl][c]".
+    fn exit_keys(&self) -> String {
+        let toggle = if self.in_peek_mode {
+            KeyAction::TogglePeek
         } else {
-            SequenceMatch::None
+            KeyAction::ToggleLookback
+        };
+        [toggle, KeyAction::ExitLookback]
+            .into_iter()
+            .filter_map(|action| self.config.keymap.key_for(action))
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+
+    fn pager_hint(&self) -> String {
+        let keys = self.exit_keys();
+        let hint = if keys.is_empty() {
+            "q to exit".to_string()
+        } else {
+            format!("{} or q to exit", keys)
+        };
+        if self.history_generations.count() > 1 {
+            format!(
+                "redraw {}  {}",
+                self.history_generations.label(self.history_view),
+                hint
+            )
+        } else {
+            hint
+        }
+    }
+
+    fn render_pager<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        // In peek mode the pager is drawn over each live frame
+        if self.in_peek_mode {
+            return self.render_vt_screen(stdout_fd);
         }
+        let hint = self.pager_hint();
+        let Some(pager) = self.pager.as_mut() else {
+            return Ok(());
+        };
+        self.output_buffer.clear();
+        self.output_buffer.extend_from_slice(SYNC_START);
+        pager.render(&hint, &mut self.output_buffer);
+        self.output_buffer.extend_from_slice(SYNC_END);
+        write_all(stdout_fd, &self.output_buffer)
     }
 
     fn enter_lookback_mode(&mut self) -> Result<()> {
@@ -905,47 +1767,165 @@ impl Proxy {
             self.history.total_bytes(),
             self.history.line_count()
         );
+        self.suspend_passthrough();
         self.in_lookback_mode = true;
-        self.lookback_cache.clear();
+        // Mouse reporting and the like would get in the way of scrolling
+        self.reset_input_modes(&io::stdout())?;
+        // Output not yet written out from the last lookback is held back
+        // again, ahead of new output
+        self.replaying_lookback_cache = false;
         self.vt_render_pending = false;
+        self.history_view = HistoryView::default();
+
+        if self.config.lookback_mode == LookbackMode::Pager {
+            self.open_pager()?;
+            return self.render_pager(&io::stdout());
+        }
 
+        self.dump_lookback(&io::stdout())
+    }
+
+    /// Turn off the input modes the child set and pop its keyboard modes.
+    /// The next render turns them back on.
+    fn reset_input_modes<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        self.vt_prev_screen = None;
+        let mut reset = input_modes_reset(self.vt_parser.screen());
+        if !self.keyboard_modes_suspended {
+            self.keyboard_modes_suspended = true;
+            reset.extend_from_slice(&self.keyboard_modes.pop_all());
+        }
+        write_all(stdout_fd, &reset)
+    }
+
+    /// Write the history being looked back at to the terminal, followed by
+    /// the lookback banner.
+    fn dump_lookback<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
         self.output_buffer.clear();
-        self.history.append_all(&mut self.output_buffer);
+        self.append_lookback_history();
         debug!(
-            "enter_lookback_mode: output_buffer_len={}",
+            "dump_lookback: view={:?} output_buffer_len={}",
+            self.history_view,
             self.output_buffer.len()
         );
 
-        let stdout_fd = io::stdout();
-        write_all(&stdout_fd, CLEAR_SCREEN)?;
-        write_all(&stdout_fd, CURSOR_HOME)?;
-        write_all(&stdout_fd, &self.output_buffer)?;
+        write_all(stdout_fd, CLEAR_SCREEN)?;
+        write_all(stdout_fd, CURSOR_HOME)?;
+        write_all(stdout_fd, &self.output_buffer)?;
 
+        let generations = if self.history_generations.count() > 1 {
+            format!(
+                " (redraw {}, [ ] to switch, a for all)",
+                self.history_generations.label(self.history_view)
+            )
+        } else {
+            String::new()
+        };
+        let typing = match self.config.lookback_input {
+            LookbackInput::Discard => "",
+            LookbackInput::Queue => ", typing is queued",
+            LookbackInput::Exit => ", typing exits",
+        };
         let exit_msg = format!(
-            "\r\n\x1b[7m--- LOOKBACK MODE: press {} or Ctrl+C to exit ---\x1b[0m\r\n",
-            self.config.lookback_key
+            "\r\n\x1b[7m--- LOOKBACK MODE{}: press {} to exit, s to export{} ---\x1b[0m\r\n",
+            generations,
+            self.exit_keys(),
+            typing
         );
-        write_all(&stdout_fd, exit_msg.as_bytes())?;
+        write_all(stdout_fd, exit_msg.as_bytes())?;
+        if !self.queued_input.is_empty() {
+            self.show_queued_input(stdout_fd)?;
+        }
 
         Ok(())
     }
 
+    /// Show history over the top of the live screen. Unlike lookback mode,
+    /// the child's output keeps being rendered underneath.
+    fn enter_peek_mode<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        debug!(
+            "enter_peek_mode: history_bytes={} lines={}",
+            self.history.total_bytes(),
+            self.history.line_count()
+        );
+        self.suspend_passthrough();
+        self.in_peek_mode = true;
+        self.history_view = HistoryView::default();
+        self.open_pager()?;
+        self.render_vt_screen(stdout_fd)
+    }
+
+    fn exit_peek_mode<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        debug!("exit_peek_mode");
+        self.close_peek()?;
+        // Force full render to replace the overlay
+        self.vt_prev_screen = None;
+        self.render_vt_screen(stdout_fd)
+    }
+
+    /// Leave peek mode without redrawing, sending on what was queued.
+    fn close_peek(&mut self) -> Result<()> {
+        if !self.in_peek_mode {
+            return Ok(());
+        }
+        self.in_peek_mode = false;
+        self.pager = None;
+        self.resume_passthrough();
+        let queued = std::mem::take(&mut self.queued_input);
+        if !queued.is_empty() {
+            debug!("close_peek: sending {} queued bytes", queued.len());
+            self.pty_input.send(&self.pty_master, &queued)?;
+        }
+        Ok(())
+    }
+
     fn exit_lookback_mode<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
         debug!(
             "exit_lookback_mode: cached_len={}",
             self.lookback_cache.len()
         );
         self.in_lookback_mode = false;
+        self.pager = None;
 
-        // Process cached output through VT to update screen state
-        let cached = std::mem::take(&mut self.lookback_cache);
-        if !cached.is_empty() {
-            debug!(
-                "exit_lookback_mode: processing {} cached bytes",
-                cached.len()
-            );
-            self.process_output(&cached, stdout_fd)?;
+        let side_channels = std::mem::take(&mut self.caught_up_side_channels);
+        write_all(stdout_fd, &side_channels)?;
+
+        let queued = std::mem::take(&mut self.queued_input);
+        if !queued.is_empty() {
+            debug!("exit_lookback_mode: sending {} queued bytes", queued.len());
+            self.pty_input.send(&self.pty_master, &queued)?;
+        }
+
+        // Output cached in memory is processed now. Spilled output can be
+        // far larger, so it is written out from the event loop instead.
+        self.replaying_lookback_cache = true;
+        while self.replaying_lookback_cache && !self.lookback_cache.is_spilling() {
+            self.continue_lookback_replay(stdout_fd)?;
         }
+        Ok(())
+    }
+
+    /// Process the next chunk of output held back for lookback, and finish
+    /// leaving lookback once it is all out.
+    fn continue_lookback_replay<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        if !self.replaying_lookback_cache {
+            return Ok(());
+        }
+        self.replaying_lookback_cache = false;
+        let chunk = self
+            .lookback_cache
+            .next_chunk()
+            .context("reading held back output")?;
+        let Some(chunk) = chunk else {
+            return self.finish_lookback_exit(stdout_fd);
+        };
+        debug!("continue_lookback_replay: {} bytes", chunk.len());
+        self.process_output(&chunk, stdout_fd)?;
+        self.replaying_lookback_cache = true;
+        Ok(())
+    }
+
+    fn finish_lookback_exit<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
+        self.lookback_cache = LookbackCache::new(self.config.lookback_cache_bytes);
 
         // Reset sync block state
         self.in_sync_block = false;
@@ -954,10 +1934,11 @@ impl Proxy {
         self.forward_winsize()?;
 
         // Force full render since terminal was showing history
-        debug!("exit_lookback_mode: rendering VT screen");
+        debug!("finish_lookback_exit: rendering VT screen");
         self.vt_prev_screen = None;
         self.render_vt_screen(stdout_fd)?;
 
+        self.resume_passthrough();
         Ok(())
     }
 
@@ -967,12 +1948,19 @@ impl Proxy {
                 "forward_winsize: rows={} cols={}",
                 winsize.ws_row, winsize.ws_col
             );
-            // Resize VT emulator
-            self.vt_parser
-                .screen_mut()
-                .set_size(winsize.ws_row, winsize.ws_col);
+            // Resize VT emulator. Resizing clears the soft-wrap flags of every
+            // row, which screen history relies on, so skip it if nothing changed
+            if self.vt_parser.screen().size() != (winsize.ws_row, winsize.ws_col) {
+                self.vt_parser
+                    .screen_mut()
+                    .set_size(winsize.ws_row, winsize.ws_col);
+            }
             // Force full render on next frame since size changed
             self.vt_prev_screen = None;
+            if self.pager.is_some() {
+                self.open_pager()?;
+                self.render_pager(&io::stdout())?;
+            }
             // Forward to child process
             unsafe {
                 libc::ioctl(
@@ -985,6 +1973,27 @@ impl Proxy {
         Ok(())
     }
 
+    /// Act on the signals received since the last check. Signals that end
+    /// the session are passed on to the child, and the session is saved on
+    /// the way out. Returns true if the terminal was closed.
+    fn handle_signals(&mut self) -> Result<bool> {
+        if SIGWINCH_RECEIVED.swap(false, Ordering::SeqCst) {
+            self.forward_winsize()?;
+        }
+        if SIGINT_RECEIVED.swap(false, Ordering::SeqCst) {
+            self.forward_signal(Signal::SIGINT);
+        }
+        if SIGTERM_RECEIVED.swap(false, Ordering::SeqCst) {
+            self.forward_signal(Signal::SIGTERM);
+        }
+        if SIGHUP_RECEIVED.swap(false, Ordering::SeqCst) {
+            debug!("handle_signals: terminal hung up");
+            self.forward_signal(Signal::SIGHUP);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     fn forward_signal(&self, signal: Signal) {
         let pid = Pid::from_raw(self.child.id() as i32);
         let _ = kill(pid, signal);
@@ -1003,6 +2012,9 @@ impl Drop for Proxy {
         if let Some(ref termios) = self.original_termios {
             let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, termios);
         }
+        if let Err(e) = self.save_session() {
+            eprintln!("Failed to save session history: {:#}", e);
+        }
     }
 }
 
@@ -1060,6 +2072,7 @@ fn setup_signal_handlers() -> Result<()> {
     setup_signal_handler(Signal::SIGWINCH, handle_sigwinch)?;
     setup_signal_handler(Signal::SIGINT, handle_sigint)?;
     setup_signal_handler(Signal::SIGTERM, handle_sigterm)?;
+    setup_signal_handler(Signal::SIGHUP, handle_sighup)?;
     Ok(())
 }
 
@@ -1083,267 +2096,386 @@ fn write_all<F: AsFd>(fd: &F, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
-fn nix_read<F: AsFd>(fd: &F, buf: &mut [u8]) -> Result<usize, Errno> {
-    read(fd.as_fd(), buf)
+/// Sequences that return the terminal from the input modes of `screen` to
+/// the defaults.
+fn input_modes_reset(screen: &vt100::Screen) -> Vec<u8> {
+    vt100::Parser::default().screen().input_mode_diff(screen)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    // Helper to test Kitty tracking using the real update_kitty_mode_helper
-    struct KittyTracker {
-        parser: TermwizParser,
-        mode_supported: bool,
-        mode_stack: u32,
+/// Notice shown after the first press of a confirm key.
+fn confirm_message(key: &KeyCombination) -> String {
+    let interrupt = KeyCombination {
+        code: KeyCode::Char('c'),
+        modifiers: Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        },
+    };
+    if *key == interrupt {
+        format!("press {} again to interrupt Claude", key)
+    } else {
+        format!("press {} again to send it", key)
     }
+}
 
-    impl KittyTracker {
-        fn new() -> Self {
-            Self {
-                parser: TermwizParser::new(),
-                mode_supported: false,
-                mode_stack: 0,
-            }
-        }
-
-        fn mode_enabled(&self) -> bool {
-            self.mode_stack > 0
-        }
-
-        fn process_output(&mut self, data: &[u8]) {
-            // Uses the real production function
-            Proxy::update_kitty_mode_helper(
-                &mut self.parser,
-                &mut self.mode_stack,
-                self.mode_supported,
-                data,
-            );
-        }
-
-        fn process_input(&mut self, data: &[u8]) {
-            // Kitty support detection from query response (CSI ? flags u)
-            // This is done separately in detect_kitty_support() at startup
-            if self.mode_supported {
-                return;
-            }
-            let actions = self.parser.parse_as_vec(data);
-            for action in actions {
-                if let Action::CSI(CSI::Keyboard(Keyboard::ReportKittyState(_))) = action {
-                    self.mode_supported = true;
-                    return;
+/// Printable preview of typed input, keeping the last `width` characters.
+/// Backspace removes the previous character and escape sequences (arrow
+/// keys and the like) are left out.
+fn input_preview(input: &[u8], width: usize) -> String {
+    let text = String::from_utf8_lossy(input);
+    let mut preview: Vec<char> = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
                 }
+                Some('O') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\x7f' | '\x08' => {
+                preview.pop();
+            }
+            '\r' | '\n' => preview.push('\u{23ce}'),
+            '\t' => preview.push(' '),
+            c if c.is_control() => {
+                preview.push('^');
+                preview.push(char::from((c as u8) ^ 0x40));
             }
+            c => preview.push(c),
         }
     }
+    if preview.len() > width {
+        let skip = preview.len() - width.saturating_sub(1);
+        std::iter::once('\u{2026}')
+            .chain(preview[skip..].iter().copied())
+            .collect()
+    } else {
+        preview.into_iter().collect()
+    }
+}
 
-    // Tests for Kitty keyboard protocol tracking
+fn nix_read<F: AsFd>(fd: &F, buf: &mut [u8]) -> Result<usize, Errno> {
+    read(fd.as_fd(), buf)
+}
 
-    #[test]
-    fn test_kitty_initially_disabled() {
-        let tracker = KittyTracker::new();
-        assert!(!tracker.mode_enabled());
-        assert!(!tracker.mode_supported);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_proxy(config: ProxyConfig) -> Proxy {
+        test_proxy_with_child(config, Command::new("true").spawn().unwrap())
     }
 
-    #[test]
-    fn test_kitty_support_detected_from_query_response() {
-        let mut tracker = KittyTracker::new();
-        // Terminal responds to query with CSI ? flags u
-        tracker.process_input(b"\x1b[?1u");
-        assert!(tracker.mode_supported);
+    fn test_proxy_with_child(config: ProxyConfig, child: Child) -> Proxy {
+        test_proxy_with_tty(config, child).0
     }
 
-    #[test]
-    fn test_kitty_push_increments_stack() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        // CSI > 1 u = push with flags
-        tracker.process_output(b"\x1b[>1u");
-        assert_eq!(tracker.mode_stack, 1);
-        assert!(tracker.mode_enabled());
+    /// A proxy along with the child's end of its pty, in raw mode so what
+    /// the child is sent can be read back as is.
+    fn test_proxy_with_tty(config: ProxyConfig, child: Child) -> (Proxy, OwnedFd) {
+        let winsize = Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = openpty(&winsize, None).unwrap();
+        let mut termios = tcgetattr(&pty.slave).unwrap();
+        cfmakeraw(&mut termios);
+        tcsetattr(&pty.slave, SetArg::TCSANOW, &termios).unwrap();
+        set_nonblocking(&pty.slave).unwrap();
+        let proxy = Proxy::new(config, pty.master, child, winsize, None, None);
+        (proxy, pty.slave)
     }
 
-    #[test]
-    fn test_kitty_push_requires_support() {
-        let mut tracker = KittyTracker::new();
-        // Push without support detection - should be ignored
-        tracker.process_output(b"\x1b[>1u");
-        assert_eq!(tracker.mode_stack, 0);
-        assert!(!tracker.mode_enabled());
+    fn read_available(fd: &OwnedFd) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n @ 1..) = read(fd, &mut buf) {
+            output.extend_from_slice(&buf[..n]);
+        }
+        output
     }
 
     #[test]
-    fn test_kitty_pop_decrements_stack() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        tracker.process_output(b"\x1b[>1u"); // push
-        tracker.process_output(b"\x1b[<u"); // pop 1
-        assert_eq!(tracker.mode_stack, 0);
-        assert!(!tracker.mode_enabled());
+    fn test_full_lookback_cache_caught_up_without_writing() {
+        let mut proxy = test_proxy(ProxyConfig {
+            lookback_cache_bytes: 16,
+            ..Default::default()
+        });
+        let (stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        set_nonblocking(&stdout_read).unwrap();
+        proxy.passthrough = true;
+        proxy.in_lookback_mode = true;
+
+        proxy
+            .process_output(
+                b"\x1b]0;title\x07one\r\ntwo\r\nthree\r\n\x1b[?10",
+                &stdout_write,
+            )
+            .unwrap();
+        assert!(read_available(&stdout_read).is_empty());
+        let mut history = Vec::new();
+        proxy.history.append_all(&mut history);
+        let history = String::from_utf8_lossy(&history);
+        assert!(history.contains("one\r\ntwo\r\nthree\r\n"), "{:?}", history);
+        assert!(!history.contains("\x1b[?10"), "{:?}", history);
+
+        // The start of the alt screen switch was kept in the cache
+        proxy
+            .process_output(b"49h\x1b[2Jfull screen", &stdout_write)
+            .unwrap();
+        assert!(read_available(&stdout_read).is_empty());
+
+        // Spilled output is written out from the event loop, with output
+        // that arrives meanwhile queued behind it
+        proxy.exit_lookback_mode(&stdout_write).unwrap();
+        assert!(proxy.replaying_lookback_cache);
+        proxy.process_output(b" more", &stdout_write).unwrap();
+        while proxy.replaying_lookback_cache {
+            proxy.continue_lookback_replay(&stdout_write).unwrap();
+        }
+        let output = String::from_utf8_lossy(&read_available(&stdout_read)).into_owned();
+        assert!(output.starts_with("\x1b]0;title\x1b\\"), "{:?}", output);
+        assert!(output.contains("\x1b[?1049h"), "{:?}", output);
+        assert!(output.contains("\x1b[2Jfull screen more"), "{:?}", output);
+        assert!(proxy.vt_parser.screen().alternate_screen());
+        assert!(proxy.lookback_cache.is_empty());
     }
 
     #[test]
-    fn test_kitty_pop_with_count() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        tracker.process_output(b"\x1b[>1u"); // push
-        tracker.process_output(b"\x1b[>1u"); // push
-        tracker.process_output(b"\x1b[>1u"); // push
-        assert_eq!(tracker.mode_stack, 3);
-        tracker.process_output(b"\x1b[<2u"); // pop 2
-        assert_eq!(tracker.mode_stack, 1);
-        assert!(tracker.mode_enabled());
+    fn test_lookback_keeps_links_from_sync_blocks() {
+        let mut proxy = test_proxy(ProxyConfig::default());
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        let mut block = SYNC_START.to_vec();
+        block.extend_from_slice(b"see \x1b]8;;https://example.com\x1b\\docs\x1b]8;;\x1b\\\r\n");
+        block.extend_from_slice(SYNC_END);
+        proxy.process_output(&block, &stdout_write).unwrap();
+
+        proxy.output_buffer.clear();
+        proxy.append_lookback_history();
+        let lookback = String::from_utf8_lossy(&proxy.output_buffer);
+        assert!(
+            lookback.contains("\x1b]8;;https://example.com\x1b\\docs\x1b]8;;\x1b\\"),
+            "{:?}",
+            lookback
+        );
     }
 
     #[test]
-    fn test_kitty_pop_saturates_at_zero() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        tracker.process_output(b"\x1b[>1u"); // push
-        tracker.process_output(b"\x1b[<5u"); // pop 5 (more than we have)
-        assert_eq!(tracker.mode_stack, 0);
-        assert!(!tracker.mode_enabled());
+    fn test_session_saved_on_drop_with_lookback_output() {
+        let dir =
+            std::env::temp_dir().join(format!("claude-chill-test-drop-{}", std::process::id()));
+        let mut proxy = test_proxy(ProxyConfig {
+            session_dir: Some(dir.clone()),
+            ..Default::default()
+        });
+        proxy.session = Some(SessionMeta::start("claude".into(), "/".into()));
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        proxy.process_output(b"before\r\n", &stdout_write).unwrap();
+        proxy.in_lookback_mode = true;
+        proxy
+            .process_output(b"held back\r\n", &stdout_write)
+            .unwrap();
+        drop(proxy);
+
+        let store = SessionStore::new(dir.clone());
+        let history = store.read_history(&store.find(None).unwrap()).unwrap();
+        let history = String::from_utf8_lossy(&history);
+        assert!(history.contains("before\r\nheld back\r\n"), "{:?}", history);
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_kitty_split_sequence_across_buffers() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        // Feed the sequence in parts
-        tracker.process_output(b"\x1b[>");
-        tracker.process_output(b"1u");
-        assert_eq!(tracker.mode_stack, 1);
+    fn test_export_includes_earlier_generations() {
+        let mut proxy = test_proxy(ProxyConfig::default());
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        proxy.process_output(b"first\r\n", &stdout_write).unwrap();
+        proxy.start_history_generation();
+        proxy.process_output(b"second\r\n", &stdout_write).unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "claude-chill-test-generations-{}.txt",
+            std::process::id()
+        ));
+        proxy.export_history(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let first = text.find("first").expect(&text);
+        let second = text.find("second").expect(&text);
+        assert!(first < second, "{:?}", text);
     }
 
     #[test]
-    fn test_kitty_multiple_sequences_in_one_buffer() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        // Push twice, pop once, all in one buffer
-        tracker.process_output(b"\x1b[>1u\x1b[>1u\x1b[<u");
-        assert_eq!(tracker.mode_stack, 1);
+    fn test_session_saved_after_sighup() {
+        let dir =
+            std::env::temp_dir().join(format!("claude-chill-test-sighup-{}", std::process::id()));
+        let child = Command::new("sleep").arg("10").spawn().unwrap();
+        let mut proxy = test_proxy_with_child(
+            ProxyConfig {
+                session_dir: Some(dir.clone()),
+                ..Default::default()
+            },
+            child,
+        );
+        proxy.session = Some(SessionMeta::start("claude".into(), "/".into()));
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        proxy
+            .process_output(b"before hangup\r\n", &stdout_write)
+            .unwrap();
+
+        SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+        assert!(proxy.handle_signals().unwrap());
+        let status = proxy.wait_child().unwrap();
+        assert_eq!(status, 128 + libc::SIGHUP);
+        drop(proxy);
+
+        let store = SessionStore::new(dir.clone());
+        let history = store.read_history(&store.find(None).unwrap()).unwrap();
+        assert!(String::from_utf8_lossy(&history).contains("before hangup\r\n"));
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_kitty_mixed_with_other_sequences() {
-        let mut tracker = KittyTracker::new();
-        tracker.mode_supported = true;
-        // Kitty push mixed with cursor moves and SGR
-        tracker.process_output(b"\x1b[H\x1b[>1u\x1b[31m\x1b[2J");
-        assert_eq!(tracker.mode_stack, 1);
+    fn test_passthrough_suspended_while_peek_is_open() {
+        let mut proxy = test_proxy(ProxyConfig::default());
+        let (stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        set_nonblocking(&stdout_read).unwrap();
+        proxy.passthrough = true;
+        proxy.suspend_passthrough();
+        proxy.in_peek_mode = true;
+
+        // Rendered under the overlay rather than written as is
+        proxy.process_output(b"hello\r\n", &stdout_write).unwrap();
+        assert!(read_available(&stdout_read).is_empty());
+        assert!(proxy.vt_render_pending);
+
+        proxy.close_peek().unwrap();
+        assert!(proxy.passthrough);
+        proxy.process_output(b"raw", &stdout_write).unwrap();
+        assert_eq!(read_available(&stdout_read), b"raw");
     }
 
     #[test]
-    fn test_kitty_typical_session_flow() {
-        let mut tracker = KittyTracker::new();
-        // 1. Terminal responds to query
-        tracker.process_input(b"\x1b[?1u");
-        assert!(tracker.mode_supported);
-        assert!(!tracker.mode_enabled());
-        // 2. App pushes keyboard mode
-        tracker.process_output(b"\x1b[>1u");
-        assert!(tracker.mode_enabled());
-        // 3. App pops keyboard mode on exit
-        tracker.process_output(b"\x1b[<u");
-        assert!(!tracker.mode_enabled());
-    }
-
-    // Tests for sequence matching (used for lookback key detection)
-
-    fn check_sequence(buffer: &[u8], byte: u8, sequence: &[u8]) -> SequenceMatch {
-        let mut buf = buffer.to_vec();
-        buf.push(byte);
-        if buf.len() > sequence.len() {
-            let excess = buf.len() - sequence.len();
-            buf.drain(..excess);
-        }
-        if buf.as_slice() == sequence {
-            SequenceMatch::Complete
-        } else if sequence.starts_with(&buf) {
-            SequenceMatch::Partial
-        } else {
-            SequenceMatch::None
-        }
+    fn test_paste_in_peek_follows_lookback_input() {
+        let mut proxy = test_proxy(ProxyConfig {
+            lookback_input: LookbackInput::Queue,
+            ..Default::default()
+        });
+        let (stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        set_nonblocking(&stdout_read).unwrap();
+        let status = |fd: &OwnedFd| String::from_utf8_lossy(&read_available(fd)).into_owned();
+        proxy.in_peek_mode = true;
+        proxy.pager = Some(Pager::new(b"", 4, 40));
+        proxy.forward_paste(b"hello", &stdout_write).unwrap();
+        assert_eq!(proxy.queued_input, b"hello");
+        assert!(status(&stdout_read).contains("queued: hello"));
+        proxy.close_peek().unwrap();
+        assert!(proxy.queued_input.is_empty());
+
+        proxy.config.lookback_input = LookbackInput::Discard;
+        proxy.in_lookback_mode = true;
+        proxy.pager = Some(Pager::new(b"", 4, 40));
+        proxy.forward_paste(b"hello", &stdout_write).unwrap();
+        assert!(proxy.queued_input.is_empty());
+        assert!(status(&stdout_read).contains("paste discarded"));
     }
 
     #[test]
-    fn test_sequence_match_complete_single_byte() {
-        // Single byte sequence (legacy Ctrl+6 = 0x1E)
-        let sequence = &[0x1E];
-        assert_eq!(check_sequence(&[], 0x1E, sequence), SequenceMatch::Complete);
+    fn test_dump_lookback_commands_matched_per_key() {
+        let mut proxy = test_proxy(ProxyConfig::default());
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        proxy.in_lookback_mode = true;
+
+        // Typed in the same read as other input
+        let keys = proxy.input_decoder.decode(b"xa");
+        proxy.handle_keys(keys, &stdout_write).unwrap();
+        assert_eq!(proxy.history_view, HistoryView::Joined);
+
+        // Once typing is queued, command keys are typing too
+        proxy.history_view = HistoryView::default();
+        proxy.config.lookback_input = LookbackInput::Queue;
+        let keys = proxy.input_decoder.decode(b"xa");
+        proxy.handle_keys(keys, &stdout_write).unwrap();
+        assert_eq!(proxy.history_view, HistoryView::default());
+        assert_eq!(proxy.queued_input, b"xa");
     }
 
     #[test]
-    fn test_sequence_match_complete_multi_byte() {
-        // Multi-byte Kitty sequence: ESC [ 5 4 ; 5 u
-        let sequence = b"\x1b[54;5u";
-        let mut buffer = Vec::new();
-        for &byte in &sequence[..sequence.len() - 1] {
-            let result = check_sequence(&buffer, byte, sequence);
-            assert_eq!(result, SequenceMatch::Partial);
-            buffer.push(byte);
-            if buffer.len() > sequence.len() {
-                buffer.drain(..buffer.len() - sequence.len());
+    fn test_hotkey_matched_in_every_protocol() {
+        let mut proxy = test_proxy(ProxyConfig::default());
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        // Legacy, Kitty, Kitty with CapsLock, and split across reads
+        let inputs: [&[&[u8]]; 4] = [
+            &[b"\x1e"],
+            &[b"\x1b[54;5u"],
+            &[b"\x1b[54;69u"],
+            &[b"\x1b[5", b"4;5", b"u"],
+        ];
+        for reads in inputs {
+            proxy.in_lookback_mode = true;
+            for data in reads {
+                proxy.process_input(data, &stdout_write).unwrap();
             }
+            assert!(!proxy.in_lookback_mode, "{:?}", reads);
         }
-        // Final byte completes the sequence
-        assert_eq!(
-            check_sequence(&buffer, sequence[sequence.len() - 1], sequence),
-            SequenceMatch::Complete
-        );
     }
 
     #[test]
-    fn test_sequence_match_partial() {
-        let sequence = b"\x1b[54;5u";
-        assert_eq!(check_sequence(&[], 0x1b, sequence), SequenceMatch::Partial);
-        assert_eq!(
-            check_sequence(&[0x1b], b'[', sequence),
-            SequenceMatch::Partial
-        );
-        assert_eq!(
-            check_sequence(&[0x1b, b'['], b'5', sequence),
-            SequenceMatch::Partial
-        );
+    fn test_non_key_input_reaches_child_unchanged() {
+        let child = Command::new("true").spawn().unwrap();
+        let (mut proxy, tty) = test_proxy_with_tty(ProxyConfig::default(), child);
+        let (_stdout_read, stdout_write) = nix::unistd::pipe().unwrap();
+        // A key release, an X10 mouse report and a paste, each holding the
+        // lookback key's legacy byte, and an SGR mouse report
+        let input: &[u8] = b"a\x1b[54;5:3u\x1b[M\x1e!!\x1b[<0;1;1Mb\x1b[200~\x1e\x1b[201~";
+        let (first, rest) = input.split_at(16);
+        proxy.process_input(first, &stdout_write).unwrap();
+        proxy.process_input(rest, &stdout_write).unwrap();
+        assert!(!proxy.in_lookback_mode);
+        assert_eq!(read_available(&tty), input);
     }
 
     #[test]
-    fn test_sequence_match_none_wrong_byte() {
-        let sequence = b"\x1b[54;5u";
-        // Start with wrong byte
-        assert_eq!(check_sequence(&[], b'a', sequence), SequenceMatch::None);
-        // Wrong byte after partial match
-        assert_eq!(check_sequence(&[0x1b], b'O', sequence), SequenceMatch::None);
+    fn test_input_preview() {
+        assert_eq!(input_preview(b"fix the bug\r", 40), "fix the bug\u{23ce}");
+        assert_eq!(input_preview(b"teh\x7f\x7fhe", 40), "the");
+        assert_eq!(input_preview(b"a\x1b[Ab\x1bOBc\x01", 40), "abc^A");
+        assert_eq!(input_preview(b"0123456789", 5), "\u{2026}6789");
     }
 
     #[test]
-    fn test_sequence_match_buffer_rolling() {
-        // Test that the rolling buffer properly handles the case where
-        // random bytes precede the actual sequence. The buffer keeps
-        // only the last N bytes where N = sequence.len()
-        let sequence = b"\x1b[54;5u"; // 7 bytes
-        // User types random chars - no match
-        assert_eq!(check_sequence(&[], b'a', sequence), SequenceMatch::None);
-        assert_eq!(check_sequence(b"a", b'b', sequence), SequenceMatch::None);
-        // Buffer [a, b, ESC] doesn't start sequence (sequence starts with ESC)
-        assert_eq!(check_sequence(b"ab", 0x1b, sequence), SequenceMatch::None);
-        // After more typing, old bytes get trimmed from buffer
-        // When buffer finally contains just ESC at the right position, it matches
-        // But with rolling buffer, we need the EXACT prefix
-        // Fresh start: ESC alone is a partial match
-        assert_eq!(check_sequence(&[], 0x1b, sequence), SequenceMatch::Partial);
+    fn test_input_modes_reset() {
+        let mut parser = vt100::Parser::default();
+        assert!(input_modes_reset(parser.screen()).is_empty());
+        parser.process(b"\x1b[?2004h\x1b[?1000h\x1b[?1006h\x1b[?1h");
+        let reset = String::from_utf8(input_modes_reset(parser.screen())).unwrap();
+        for sequence in ["\x1b[?2004l", "\x1b[?1000l", "\x1b[?1006l", "\x1b[?1l"] {
+            assert!(reset.contains(sequence), "{:?}", reset);
+        }
+        parser.process(reset.as_bytes());
+        assert!(!parser.screen().bracketed_paste());
+        assert!(!parser.screen().application_cursor());
     }
 
     #[test]
-    fn test_sequence_match_interleaved_typing() {
-        // User types "ab" then the lookback sequence
-        let sequence = &[0x1E];
-        assert_eq!(check_sequence(&[], b'a', sequence), SequenceMatch::None);
-        assert_eq!(check_sequence(b"a", b'b', sequence), SequenceMatch::None);
+    fn test_confirm_message() {
+        let key = |raw| crate::key_parser::parse(raw).unwrap();
+        assert_eq!(
+            confirm_message(&key("[ctrl][c]")),
+            "press [ctrl][c] again to interrupt Claude"
+        );
         assert_eq!(
-            check_sequence(b"ab", 0x1E, sequence),
-            SequenceMatch::Complete
+            confirm_message(&key("[ctrl][d]")),
+            "press [ctrl][d] again to send it"
         );
     }
 }
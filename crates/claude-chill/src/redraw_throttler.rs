@@ -84,3 +84,42 @@ impl RedrawThrottler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_render_initially() {
+        let throttler = RedrawThrottler::new(50);
+        assert!(throttler.can_render());
+        assert_eq!(throttler.time_until_can_render(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_blocks_render_within_interval() {
+        let mut throttler = RedrawThrottler::new(10_000);
+        throttler.mark_rendered();
+        assert!(!throttler.can_render());
+        let wait = throttler.time_until_can_render().unwrap();
+        assert!(wait > Duration::from_millis(9_000));
+    }
+
+    #[test]
+    fn test_allows_render_after_interval() {
+        let mut throttler = RedrawThrottler::new(0);
+        throttler.mark_rendered();
+        assert!(throttler.can_render());
+        assert_eq!(throttler.time_until_can_render(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_take_pending_respects_interval() {
+        let mut throttler = RedrawThrottler::new(10_000);
+        throttler.submit(b"frame1".to_vec());
+        assert_eq!(throttler.take_pending(), Some(b"frame1".to_vec()));
+        throttler.submit(b"frame2".to_vec());
+        assert!(throttler.has_pending());
+        assert_eq!(throttler.take_pending(), None);
+    }
+}
@@ -0,0 +1,321 @@
+//! Screen-based history.
+//!
+//! Instead of storing the raw output stream, rows are taken from the VT
+//! emulator as they scroll off the top of the screen. Each row is written as
+//! plain text with SGR styling, and rows the emulator soft-wrapped are joined
+//! back into one logical line. Replaying that history at any width lets the
+//! terminal (or the pager) wrap it again, so resizes don't garble lookback.
+
+use crate::line_buffer::LineBuffer;
+
+/// Scrollback rows the emulator needs to hold rows until they are captured.
+pub const SCROLLBACK_ROWS: usize = 256;
+
+/// Output is fed to the emulator in chunks small enough that a single chunk
+/// can't scroll more rows than the scrollback holds. Only an explicit scroll
+/// (`CSI n S`) of hundreds of rows can outrun it, and those rows are lost.
+const CHUNK_BYTES: usize = 128;
+
+/// Feed `data` to the emulator, appending every row that scrolls off the top
+/// of the main screen to `history`.
+pub fn process(parser: &mut vt100::Parser, data: &[u8], history: &mut LineBuffer) {
+    let mut line = Vec::new();
+    process_rows(parser, data, |screen, row| {
+        line.clear();
+        write_row(screen, row, screen.size().1, &mut line);
+        if !screen.row_wrapped(row) {
+            line.extend_from_slice(b"\r\n");
+        }
+        history.push_bytes(&line);
+    });
+}
+
+/// Feed `data` to the emulator, calling `on_row` for every row that scrolls
+/// off the top of the main screen, oldest first. The screen is scrolled back
+/// so the row is visible at the row number passed along with it.
+pub fn process_rows<F>(parser: &mut vt100::Parser, data: &[u8], mut on_row: F)
+where
+    F: FnMut(&vt100::Screen, u16),
+{
+    for chunk in data.chunks(CHUNK_BYTES) {
+        let was_alternate = parser.screen().alternate_screen();
+        let before = scrollback_len(parser);
+        // The scrollback offset grows by one for every row scrolled off while
+        // it is non-zero, which counts new rows even once the scrollback is
+        // full and old rows are being dropped
+        parser.screen_mut().set_scrollback(1);
+        parser.process(chunk);
+
+        let new_rows = if was_alternate || parser.screen().alternate_screen() {
+            0
+        } else if before == 0 {
+            scrollback_len(parser)
+        } else {
+            parser.screen().scrollback() - 1
+        };
+        capture_rows(parser, new_rows, &mut on_row);
+        parser.screen_mut().set_scrollback(0);
+    }
+}
+
+/// Append the visible screen, without trailing blank rows, as history lines.
+pub fn append_screen(screen: &vt100::Screen, output: &mut Vec<u8>) {
+    for line in screen_lines(screen) {
+        output.extend_from_slice(&line);
+        output.extend_from_slice(b"\r\n");
+    }
+}
+
+/// The visible screen as styled logical lines, with soft-wrapped rows
+/// joined and trailing blank rows dropped.
+pub fn screen_lines(screen: &vt100::Screen) -> Vec<Vec<u8>> {
+    let cols = screen.size().1;
+    let rows = content_rows(screen);
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for row in 0..rows {
+        write_row(screen, row, cols, &mut line);
+        if !screen.row_wrapped(row) || row + 1 == rows {
+            lines.push(std::mem::take(&mut line));
+        }
+    }
+    lines
+}
+
+fn scrollback_len(parser: &mut vt100::Parser) -> usize {
+    let offset = parser.screen().scrollback();
+    parser.screen_mut().set_scrollback(usize::MAX);
+    let len = parser.screen().scrollback();
+    parser.screen_mut().set_scrollback(offset);
+    len
+}
+
+/// Pass the newest `count` scrollback rows to `on_row`, oldest first.
+fn capture_rows<F>(parser: &mut vt100::Parser, count: usize, on_row: &mut F)
+where
+    F: FnMut(&vt100::Screen, u16),
+{
+    let count = count.min(scrollback_len(parser));
+    let rows = parser.screen().size().0;
+    let mut remaining = count;
+    while remaining > 0 {
+        // With the scrollback offset at `remaining`, the top visible row is
+        // the oldest row not captured yet
+        parser.screen_mut().set_scrollback(remaining);
+        let take = remaining.min(usize::from(rows));
+        for row in 0..take as u16 {
+            on_row(parser.screen(), row);
+        }
+        remaining -= take;
+    }
+}
+
+/// Number of rows down to the last one that isn't blank.
+pub(crate) fn content_rows(screen: &vt100::Screen) -> u16 {
+    let (rows, cols) = screen.size();
+    (0..rows)
+        .rev()
+        .find(|&row| !row_is_blank(screen, row, cols))
+        .map_or(0, |row| row + 1)
+}
+
+fn row_is_blank(screen: &vt100::Screen, row: u16, cols: u16) -> bool {
+    (0..cols).all(|col| screen.cell(row, col).is_none_or(is_blank))
+}
+
+fn is_blank(cell: &vt100::Cell) -> bool {
+    !cell.has_contents() && cell.bgcolor() == vt100::Color::Default && !cell.inverse()
+}
+
+/// Write one row as text with SGR styling. Trailing blanks are dropped
+/// unless the row wraps, since then they are part of the logical line.
+fn write_row(screen: &vt100::Screen, row: u16, cols: u16, output: &mut Vec<u8>) {
+    let end = if screen.row_wrapped(row) {
+        cols
+    } else {
+        (0..cols)
+            .rev()
+            .find(|&col| screen.cell(row, col).is_some_and(|cell| !is_blank(cell)))
+            .map_or(0, |col| col + 1)
+    };
+
+    write_cells(screen, row, 0..end, output);
+}
+
+/// Write the cells in `cols` of `row` as text with SGR styling, ending
+/// with the style reset.
+pub(crate) fn write_cells(
+    screen: &vt100::Screen,
+    row: u16,
+    cols: std::ops::Range<u16>,
+    output: &mut Vec<u8>,
+) {
+    let mut styled = false;
+    let mut previous: Option<&vt100::Cell> = None;
+    for col in cols {
+        let Some(cell) = screen.cell(row, col) else {
+            continue;
+        };
+        if cell.is_wide_continuation() {
+            continue;
+        }
+        if previous.is_none_or(|p| !same_style(p, cell)) {
+            if has_style(cell) {
+                write_cell_sgr(cell, cell.inverse(), output);
+                styled = true;
+            } else if styled {
+                output.extend_from_slice(b"\x1b[0m");
+                styled = false;
+            }
+        }
+        if cell.has_contents() {
+            output.extend_from_slice(cell.contents().as_bytes());
+        } else {
+            output.push(b' ');
+        }
+        previous = Some(cell);
+    }
+    if styled {
+        output.extend_from_slice(b"\x1b[0m");
+    }
+}
+
+fn has_style(cell: &vt100::Cell) -> bool {
+    cell.bold()
+        || cell.dim()
+        || cell.italic()
+        || cell.underline()
+        || cell.inverse()
+        || cell.fgcolor() != vt100::Color::Default
+        || cell.bgcolor() != vt100::Color::Default
+}
+
+fn same_style(a: &vt100::Cell, b: &vt100::Cell) -> bool {
+    a.bold() == b.bold()
+        && a.dim() == b.dim()
+        && a.italic() == b.italic()
+        && a.underline() == b.underline()
+        && a.inverse() == b.inverse()
+        && a.fgcolor() == b.fgcolor()
+        && a.bgcolor() == b.bgcolor()
+}
+
+/// Write a full SGR sequence (starting with a reset) for a cell's style.
+pub(crate) fn write_cell_sgr(cell: &vt100::Cell, inverse: bool, out: &mut Vec<u8>) {
+    let mut params = vec!["0".to_string()];
+    if cell.bold() {
+        params.push("1".to_string());
+    }
+    if cell.dim() {
+        params.push("2".to_string());
+    }
+    if cell.italic() {
+        params.push("3".to_string());
+    }
+    if cell.underline() {
+        params.push("4".to_string());
+    }
+    if inverse {
+        params.push("7".to_string());
+    }
+    push_color_params(cell.fgcolor(), 30, &mut params);
+    push_color_params(cell.bgcolor(), 40, &mut params);
+    out.extend_from_slice(format!("\x1b[{}m", params.join(";")).as_bytes());
+}
+
+fn push_color_params(color: vt100::Color, base: u8, params: &mut Vec<String>) {
+    match color {
+        vt100::Color::Default => {}
+        vt100::Color::Idx(n) if n < 8 => params.push((base + n).to_string()),
+        vt100::Color::Idx(n) if n < 16 => params.push((base + 60 + n - 8).to_string()),
+        vt100::Color::Idx(n) => params.push(format!("{};5;{}", base + 8, n)),
+        vt100::Color::Rgb(r, g, b) => params.push(format!("{};2;{};{};{}", base + 8, r, g, b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(parser: &mut vt100::Parser, data: &[u8], history: &mut LineBuffer) -> Vec<u8> {
+        process(parser, data, history);
+        let mut output = Vec::new();
+        history.append_all(&mut output);
+        output
+    }
+
+    #[test]
+    fn test_captures_rows_scrolled_off() {
+        let mut parser = vt100::Parser::new(3, 20, SCROLLBACK_ROWS);
+        let mut history = LineBuffer::new(100);
+        let output = capture(
+            &mut parser,
+            b"one\r\ntwo\r\nthree\r\nfour\r\nfive",
+            &mut history,
+        );
+        assert_eq!(output, b"one\r\ntwo\r\n");
+
+        let output = capture(&mut parser, b"\r\nsix\r\n", &mut history);
+        assert_eq!(output, b"one\r\ntwo\r\nthree\r\nfour\r\n");
+        assert_eq!(parser.screen().scrollback(), 0);
+    }
+
+    #[test]
+    fn test_counts_rows_once_scrollback_is_full() {
+        let mut parser = vt100::Parser::new(2, 10, 4);
+        let mut history = LineBuffer::new(100);
+        let mut data = Vec::new();
+        for i in 0..20 {
+            data.extend_from_slice(format!("{}\r\n", i).as_bytes());
+        }
+        // Fed in several calls so the scrollback is full before the last one
+        for chunk in data.chunks(9) {
+            process(&mut parser, chunk, &mut history);
+        }
+        let mut output = Vec::new();
+        history.append_all(&mut output);
+        let expected: String = (0..19).map(|i| format!("{}\r\n", i)).collect();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_joins_wrapped_rows() {
+        let mut parser = vt100::Parser::new(2, 5, SCROLLBACK_ROWS);
+        let mut history = LineBuffer::new(100);
+        let output = capture(&mut parser, b"abcdefgh\r\nx\r\ny\r\n", &mut history);
+        assert_eq!(output, b"abcdefgh\r\nx\r\n");
+    }
+
+    #[test]
+    fn test_keeps_styles() {
+        let mut parser = vt100::Parser::new(2, 20, SCROLLBACK_ROWS);
+        let mut history = LineBuffer::new(100);
+        let output = capture(
+            &mut parser,
+            b"\x1b[1;31mred\x1b[0m plain\r\nb\r\nc\r\n",
+            &mut history,
+        );
+        assert_eq!(output, b"\x1b[0;1;31mred\x1b[0m plain\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_alternate_screen_not_captured() {
+        let mut parser = vt100::Parser::new(2, 10, SCROLLBACK_ROWS);
+        let mut history = LineBuffer::new(100);
+        let output = capture(
+            &mut parser,
+            b"\x1b[?1049ha\r\nb\r\nc\r\nd\r\n",
+            &mut history,
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_append_screen() {
+        let mut parser = vt100::Parser::new(4, 5, 0);
+        parser.process(b"abcdefg\r\nxy");
+        let mut output = Vec::new();
+        append_screen(parser.screen(), &mut output);
+        assert_eq!(output, b"abcdefg\r\nxy\r\n");
+    }
+}
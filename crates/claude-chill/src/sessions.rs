@@ -0,0 +1,380 @@
+//! Saved session history.
+//!
+//! When the proxy exits, its history is written to the session directory
+//! (`$XDG_STATE_HOME/claude-chill/sessions` by default) as two files that
+//! share a session id: `<id>.toml` with metadata and `<id>.log` with the
+//! filtered history bytes, ready to be replayed to a terminal.
+
+use crate::export;
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const METADATA_EXTENSION: &str = "toml";
+const HISTORY_EXTENSION: &str = "log";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMeta {
+    pub id: String,
+    pub command: String,
+    pub cwd: String,
+    /// Unix time in seconds
+    pub start: u64,
+    /// Unix time in seconds
+    pub end: u64,
+    /// Width of the terminal the history was written for
+    #[serde(default = "default_cols")]
+    pub cols: u16,
+}
+
+fn default_cols() -> u16 {
+    export::DEFAULT_EXPORT_COLS
+}
+
+impl SessionMeta {
+    /// Metadata for a session starting now. Ids sort chronologically.
+    pub fn start(command: String, cwd: String) -> Self {
+        let start = unix_now();
+        Self {
+            id: format!("{}-{}", start, std::process::id()),
+            command,
+            cwd,
+            start,
+            end: start,
+            cols: default_cols(),
+        }
+    }
+}
+
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `$XDG_STATE_HOME/claude-chill/sessions`, falling back to the local
+    /// data directory on platforms without a state directory.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|d| d.join("claude-chill").join("sessions"))
+    }
+
+    /// Write a session. History holds whatever was on screen, so only the
+    /// user can read it.
+    pub fn save(&self, meta: &SessionMeta, history: &[u8]) -> Result<()> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        let history_path = self.path(&meta.id, HISTORY_EXTENSION);
+        write_private(&history_path, history)
+            .with_context(|| format!("writing {}", history_path.display()))?;
+        let metadata = toml::to_string(meta).context("serializing session metadata")?;
+        let metadata_path = self.path(&meta.id, METADATA_EXTENSION);
+        write_private(&metadata_path, metadata.as_bytes())
+            .with_context(|| format!("writing {}", metadata_path.display()))
+    }
+
+    /// All saved sessions, oldest first.
+    pub fn list(&self) -> Result<Vec<SessionMeta>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", self.dir.display())),
+        };
+
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(METADATA_EXTENSION) {
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Ok(meta) = toml::from_str::<SessionMeta>(&content) {
+                sessions.push(meta);
+            }
+        }
+        sessions.sort_by(|a, b| (a.start, &a.id).cmp(&(b.start, &b.id)));
+        Ok(sessions)
+    }
+
+    /// Find a session by id prefix. Without a prefix, the latest session.
+    pub fn find(&self, id_prefix: Option<&str>) -> Result<SessionMeta> {
+        let sessions = self.list()?;
+        let Some(prefix) = id_prefix else {
+            return sessions.into_iter().last().context("no saved sessions");
+        };
+        let mut matches: Vec<_> = sessions
+            .into_iter()
+            .filter(|s| s.id.starts_with(prefix))
+            .collect();
+        match matches.len() {
+            0 => anyhow::bail!("no session matching '{}'", prefix),
+            1 => Ok(matches.remove(0)),
+            n => anyhow::bail!("'{}' matches {} sessions", prefix, n),
+        }
+    }
+
+    pub fn read_history(&self, meta: &SessionMeta) -> Result<Vec<u8>> {
+        let path = self.path(&meta.id, HISTORY_EXTENSION);
+        fs::read(&path).with_context(|| format!("reading {}", path.display()))
+    }
+
+    /// Delete the oldest sessions so at most `keep` remain.
+    pub fn prune(&self, keep: usize) -> Result<()> {
+        let sessions = self.list()?;
+        let excess = sessions.len().saturating_sub(keep);
+        for meta in &sessions[..excess] {
+            for extension in [HISTORY_EXTENSION, METADATA_EXTENSION] {
+                let _ = fs::remove_file(self.path(&meta.id, extension));
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, id: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, extension))
+    }
+}
+
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(data)
+}
+
+/// `sessions list`: one line per session, oldest first.
+pub fn write_list<W: Write>(store: &SessionStore, out: &mut W) -> Result<()> {
+    for meta in store.list()? {
+        writeln!(
+            out,
+            "{}  {}  {:>8}  {}  {}",
+            meta.id,
+            format_timestamp(meta.start),
+            format_duration(meta.end.saturating_sub(meta.start)),
+            meta.cwd,
+            meta.command
+        )?;
+    }
+    Ok(())
+}
+
+/// `sessions show`: the session's history, raw or with escapes stripped.
+pub fn write_show<W: Write>(
+    store: &SessionStore,
+    id_prefix: Option<&str>,
+    plain: bool,
+    out: &mut W,
+) -> Result<()> {
+    let meta = store.find(id_prefix)?;
+    let history = store.read_history(&meta)?;
+    if plain {
+        out.write_all(export::to_plain_text(&history, meta.cols).as_bytes())?;
+    } else {
+        out.write_all(&history)?;
+    }
+    Ok(())
+}
+
+/// `sessions grep`: matching lines of every session as `id:line:text`.
+/// Returns the number of matching lines.
+pub fn write_grep<W: Write>(store: &SessionStore, pattern: &str, out: &mut W) -> Result<usize> {
+    let regex = Regex::new(pattern).with_context(|| format!("invalid pattern '{}'", pattern))?;
+    let mut count = 0;
+    for meta in store.list()? {
+        let Ok(history) = store.read_history(&meta) else {
+            continue;
+        };
+        let text = export::to_plain_text(&history, meta.cols);
+        for (index, line) in text.lines().enumerate() {
+            if regex.is_match(line) {
+                writeln!(out, "{}:{}:{}", meta.id, index + 1, line)?;
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format unix seconds as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        (time % 3600) / 60
+    )
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "claude-chill-test-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn meta(id: &str, start: u64) -> SessionMeta {
+        SessionMeta {
+            id: id.to_string(),
+            command: "claude --verbose".to_string(),
+            cwd: "/home/user/project".to_string(),
+            start,
+            end: start + 75,
+            cols: 80,
+        }
+    }
+
+    #[test]
+    fn test_save_and_list() {
+        let tmp = TempDir::new("list");
+        let store = SessionStore::new(tmp.0.clone());
+        assert!(store.list().unwrap().is_empty());
+
+        store.save(&meta("200-1", 200), b"second\n").unwrap();
+        store.save(&meta("100-1", 100), b"first\n").unwrap();
+
+        let sessions = store.list().unwrap();
+        assert_eq!(sessions, vec![meta("100-1", 100), meta("200-1", 200)]);
+        assert_eq!(store.read_history(&sessions[0]).unwrap(), b"first\n");
+
+        let mode = |path: PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(tmp.0.clone()), 0o700);
+        assert_eq!(mode(tmp.0.join("100-1.log")), 0o600);
+        assert_eq!(mode(tmp.0.join("100-1.toml")), 0o600);
+    }
+
+    #[test]
+    fn test_find_by_prefix() {
+        let tmp = TempDir::new("find");
+        let store = SessionStore::new(tmp.0.clone());
+        store.save(&meta("100-1", 100), b"").unwrap();
+        store.save(&meta("150-1", 150), b"").unwrap();
+        store.save(&meta("200-1", 200), b"").unwrap();
+
+        assert_eq!(store.find(None).unwrap().id, "200-1");
+        assert_eq!(store.find(Some("15")).unwrap().id, "150-1");
+        assert!(store.find(Some("1")).is_err());
+        assert!(store.find(Some("3")).is_err());
+    }
+
+    #[test]
+    fn test_prune_keeps_newest() {
+        let tmp = TempDir::new("prune");
+        let store = SessionStore::new(tmp.0.clone());
+        for start in [100, 200, 300] {
+            store
+                .save(&meta(&format!("{}-1", start), start), b"")
+                .unwrap();
+        }
+        store.prune(2).unwrap();
+        let ids: Vec<_> = store.list().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec!["200-1", "300-1"]);
+        assert!(!tmp.0.join("100-1.log").exists());
+    }
+
+    #[test]
+    fn test_grep_and_show() {
+        let tmp = TempDir::new("grep");
+        let store = SessionStore::new(tmp.0.clone());
+        store
+            .save(&meta("100-1", 100), b"\x1b[31mError\x1b[0m: one\r\nok\r\n")
+            .unwrap();
+        store
+            .save(&meta("200-1", 200), b"fine\r\nError: two\r\n")
+            .unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(write_grep(&store, "^Error", &mut out).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "100-1:1:Error: one\n200-1:2:Error: two\n"
+        );
+
+        let mut out = Vec::new();
+        write_show(&store, Some("100"), true, &mut out).unwrap();
+        assert_eq!(out, b"Error: one\nok\n");
+    }
+
+    #[test]
+    fn test_list_output() {
+        let tmp = TempDir::new("output");
+        let store = SessionStore::new(tmp.0.clone());
+        store.save(&meta("100-1", 1_700_000_000), b"").unwrap();
+        let mut out = Vec::new();
+        write_list(&store, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "100-1  2023-11-14 22:13     1m15s  /home/user/project  claude --verbose\n"
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13");
+    }
+}
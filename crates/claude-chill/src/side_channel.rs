@@ -0,0 +1,201 @@
+//! Output that doesn't draw anything but still matters to the terminal:
+//! window titles, desktop notifications, clipboard writes, the current
+//! directory and the bell. The VT emulator has no model for them, so they
+//! would be lost when main-screen output is re-rendered. They are picked out
+//! of the output here and forwarded as the `[forward]` config allows.
+
+use crate::config::ForwardPolicy;
+use crate::history_filter::classify_side_channel;
+use std::ffi::OsString;
+use std::fmt::Write as FmtWrite;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use termwiz::escape::Action;
+use termwiz::escape::osc::OperatingSystemCommand;
+use termwiz::escape::parser::Parser;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SideChannel {
+    /// Window and icon titles (OSC 0, 1 and 2)
+    Title,
+    /// Desktop notifications (OSC 9 and OSC 777)
+    Notification,
+    /// Clipboard writes (OSC 52)
+    Clipboard,
+    /// Current directory reports (OSC 7)
+    Cwd,
+    /// BEL
+    Bell,
+}
+
+pub struct SideChannelFilter {
+    parser: Parser,
+    policy: ForwardPolicy,
+    /// The last directory the child reported, forwarded or not
+    cwd: Option<PathBuf>,
+    /// The parser was left inside an escape sequence by the last output
+    inside_sequence: bool,
+}
+
+impl SideChannelFilter {
+    pub fn new(policy: ForwardPolicy) -> Self {
+        Self {
+            parser: Parser::new(),
+            policy,
+            cwd: None,
+            inside_sequence: false,
+        }
+    }
+
+    /// The current directory from the child's last OSC 7 report.
+    pub fn cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    /// The side-channel sequences in `data` that the policy allows, in order.
+    pub fn extract(&mut self, data: &[u8]) -> Vec<u8> {
+        // Most output is plain text, which can't hold a side channel unless
+        // it continues one started earlier
+        match ends_inside_sequence(data) {
+            Some(inside) => self.inside_sequence = inside,
+            None if !self.inside_sequence => return Vec::new(),
+            None => {}
+        }
+        let mut output = String::new();
+        for action in self.parser.parse_as_vec(data) {
+            if let Action::OperatingSystemCommand(osc) = &action
+                && let OperatingSystemCommand::CurrentWorkingDirectory(url) = osc.as_ref()
+            {
+                self.cwd = path_from_file_url(url).or(self.cwd.take());
+            }
+            if classify_side_channel(&action).is_some_and(|c| self.policy.allows(c)) {
+                let _ = write!(output, "{}", action);
+            }
+        }
+        output.into_bytes()
+    }
+}
+
+/// Whether the parser is left inside an escape sequence after `data`, or
+/// None if `data` has no ESC or BEL and so leaves it where it was.
+fn ends_inside_sequence(data: &[u8]) -> Option<bool> {
+    let Some(esc) = memchr::memrchr(0x1b, data) else {
+        // A BEL ends any OSC in progress
+        return memchr::memchr(0x07, data).map(|_| false);
+    };
+    let body = &data[esc + 1..];
+    Some(match body.first() {
+        None => true,
+        // CSI runs to its final byte
+        Some(b'[') => !body[1..].iter().any(|b| (0x40..=0x7e).contains(b)),
+        // Strings run to a BEL or ST, and the ESC of an ST would be the last
+        Some(b']' | b'P' | b'X' | b'^' | b'_') => !body.contains(&0x07),
+        // Intermediates such as charset designations take one more byte
+        Some(0x20..=0x2f) => body.len() < 2,
+        Some(_) => false,
+    })
+}
+
+/// The path in a `file://host/path` URL, percent-decoded.
+fn path_from_file_url(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extracts_side_channels() {
+        let mut filter = SideChannelFilter::new(ForwardPolicy::default());
+        let output = filter.extract(
+            b"text\x1b]0;my title\x07\x1b[31mred\x1b]9;Claude needs your attention\x1b\\\x07\
+              \x1b]7;file://host/tmp\x07\x1b]52;c;aGk=\x07",
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("my title"));
+        assert!(output.contains("9;Claude needs your attention"));
+        assert!(output.contains("7;file://host/tmp"));
+        assert!(output.contains("52;c;aGk="));
+        // The BEL between the notification and the cwd report
+        assert_eq!(output.matches('\x07').count(), 1);
+        assert!(!output.contains("text"));
+        assert!(!output.contains("red"));
+    }
+
+    #[test]
+    fn test_policy_denies_channels() {
+        let policy = ForwardPolicy {
+            clipboard: false,
+            bell: false,
+            ..ForwardPolicy::default()
+        };
+        let mut filter = SideChannelFilter::new(policy);
+        let output = filter.extract(b"\x1b]52;c;aGk=\x07\x07\x1b]2;title\x07");
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("52;"));
+        assert!(output.contains("2;title"));
+        assert_eq!(output, "\x1b]2;title\x1b\\");
+    }
+
+    #[test]
+    fn test_sequence_split_across_output() {
+        let mut filter = SideChannelFilter::new(ForwardPolicy::default());
+        assert!(filter.extract(b"\x1b]777;notify;Claude;").is_empty());
+        let output = filter.extract(b"done\x07");
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("777;notify;Claude;done")
+        );
+    }
+
+    #[test]
+    fn test_plain_output_continues_open_sequence() {
+        let mut filter = SideChannelFilter::new(ForwardPolicy::default());
+        assert!(filter.extract(b"plain text").is_empty());
+        assert!(filter.extract(b"\x1b[31mred\x1b]2;long ").is_empty());
+        assert!(filter.extract(b"window title").is_empty());
+        let output = filter.extract(b"\x1b\\more text");
+        assert_eq!(output, b"\x1b]2;long window title\x1b\\");
+        assert!(!filter.extract(b"\x1b]2;x\x07plain").is_empty());
+        assert!(filter.extract(b"plain again").is_empty());
+    }
+
+    #[test]
+    fn test_tracks_reported_cwd() {
+        let policy = ForwardPolicy {
+            cwd: false,
+            ..ForwardPolicy::default()
+        };
+        let mut filter = SideChannelFilter::new(policy);
+        assert_eq!(filter.cwd(), None);
+        assert!(
+            filter
+                .extract(b"\x1b]7;file://host/home/me/my%20project\x07")
+                .is_empty()
+        );
+        assert_eq!(filter.cwd(), Some(Path::new("/home/me/my project")));
+        filter.extract(b"\x1b]7;not a url\x07");
+        assert_eq!(filter.cwd(), Some(Path::new("/home/me/my project")));
+    }
+
+    #[test]
+    fn test_queries_are_not_forwarded() {
+        let mut filter = SideChannelFilter::new(ForwardPolicy::default());
+        assert!(filter.extract(b"\x1b]52;c;?\x07\x1b]10;?\x07").is_empty());
+    }
+}